@@ -0,0 +1,501 @@
+use crate::ir::*;
+use crate::resolve::symbol;
+use crate::{diagnostics::Diagnostic, OperatorType};
+
+// Emits x86-64 assembly for GNU as from the IR, following the System V calling convention. The
+// output is a standalone program with its own _start and print, so it only needs as and ld, not
+// a C compiler.
+//
+// Every slot gets an 8 byte stack slot below rbp, arrays get one for every element with the first
+// element lowest. Virtual registers go below the slots and share words when their live ranges
+// don't overlap, which keeps frames small enough for deep recursion. Instructions load their
+// operands into rax and rcx and store the result straight back.
+
+const ARG_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+
+// Frame offsets are 32 bit displacements, so a frame can hold this many 8 byte words at most
+const MAX_FRAME_WORDS: usize = (i32::MAX as usize) / 8 - 1;
+
+pub struct AsmGenerator {
+    program: IrProgram,
+    code: String,
+    strings: Vec<String>, // String constants, emitted to .rodata as .Lstr{index}
+    labels: usize,        // Labels handed out by new_label
+}
+
+impl AsmGenerator {
+    pub fn new(program: IrProgram) -> Self {
+        Self {
+            program,
+            code: String::new(),
+            strings: vec![],
+            labels: 0,
+        }
+    }
+
+    pub fn generate(&mut self) -> Result<String, Diagnostic> {
+        // Every slot and register is a single word, a struct would have to be split up into its
+        // fields
+        if let Some(decl) = self.program.structs.first() {
+            return Err(
+                Diagnostic::error("E0300", "structs aren't supported by the asm backend")
+                    .with_label(&decl.span, "struct declared here")
+                    .with_note("use the C backend or `--interpret` instead"),
+            );
+        }
+
+        self.code += ".intel_syntax noprefix\n";
+        self.code += ".globl _start\n\n";
+        self.code += ".text\n";
+        self.code += START;
+        self.code += PRINT;
+        self.code += PRINT_STR;
+        self.code += FAIL;
+        for (i, func) in self.program.functions.clone().iter().enumerate() {
+            self.generate_function(i, func)?;
+        }
+
+        if !self.strings.is_empty() {
+            self.code += ".section .rodata\n";
+            for (i, value) in self.strings.iter().enumerate() {
+                self.code += &format!(".Lstr{}:\n    .asciz {}\n", i, asm_string(value));
+            }
+        }
+
+        Ok(std::mem::take(&mut self.code))
+    }
+
+    fn emit(&mut self, instr: &str) {
+        self.code += "    ";
+        self.code += instr;
+        self.code += "\n";
+    }
+
+    // A label for jumps inside a single instruction, like around a check
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".Lx{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.code += &format!("{}:\n", label);
+    }
+
+    // Loads message into rdi and calls routine, which stops the program with it
+    fn fail(&mut self, routine: &str, message: &str) {
+        self.strings.push(message.to_string());
+        self.emit(&format!("lea rdi, [rip + .Lstr{}]", self.strings.len() - 1));
+        self.emit(&format!("call {}", routine));
+    }
+
+    // idiv faults on the smallest int divided by -1, so that is done by hand to let it wrap
+    // around like in the other backends. Dividing by -1 is negating, and the remainder is 0
+    fn divide(&mut self, op: OperatorType) {
+        let idiv = self.new_label();
+        let done = self.new_label();
+        self.emit("cmp rcx, -1");
+        self.emit(&format!("jne {}", idiv));
+        match op {
+            OperatorType::Division => self.emit("neg rax"),
+            _ => self.emit("xor eax, eax"),
+        }
+        self.emit(&format!("jmp {}", done));
+        self.place(&idiv);
+        self.emit("cqo");
+        self.emit("idiv rcx");
+        if op == OperatorType::Modulo {
+            self.emit("mov rax, rdx");
+        }
+        self.place(&done);
+    }
+
+    fn generate_function(&mut self, index: usize, func: &IrFunction) -> Result<(), Diagnostic> {
+        let too_large = || {
+            Diagnostic::error(
+                "E0301",
+                format!("the stack frame of `{}` is too large", func.name),
+            )
+            .with_note("split its arrays up between more functions")
+        };
+        let mut offsets = vec![];
+        let mut words: usize = 0;
+        for typ in &func.slot_types {
+            let size = match typ {
+                SlotType::Array(len) => *len,
+                SlotType::Value(_) => 1,
+            };
+            words = words
+                .checked_add(size)
+                .filter(|&words| words <= MAX_FRAME_WORDS)
+                .ok_or_else(too_large)?;
+            offsets.push(words * 8);
+        }
+        // Every register is a single word, so any two can share one
+        let (reg_words, reg_count) = func.share_registers(|_| ());
+        if words + reg_count > MAX_FRAME_WORDS {
+            return Err(too_large());
+        }
+        let slot = |s: Slot| format!("qword ptr [rbp - {}]", offsets[s]);
+        let reg = |r: Reg| {
+            format!(
+                "qword ptr [rbp - {}]",
+                (words + reg_words[r].unwrap() + 1) * 8
+            )
+        };
+        let label = |b: BlockId| format!(".L{}_{}", index, b);
+
+        // rsp has to stay 16 byte aligned at calls
+        let frame = ((words + reg_count) * 8).div_ceil(16) * 16;
+
+        self.code += &format!("{}:\n", symbol(&func.name));
+        self.emit("push rbp");
+        self.emit("mov rbp, rsp");
+        if frame > 0 {
+            self.emit(&format!("sub rsp, {}", frame));
+        }
+
+        // The first six arguments come in registers, the rest are above the return address
+        for (i, &s) in func.params.iter().chain(func.captures.iter()).enumerate() {
+            if i < ARG_REGS.len() {
+                self.emit(&format!("mov {}, {}", slot(s), ARG_REGS[i]));
+            } else {
+                self.emit(&format!("mov rax, qword ptr [rbp + {}]", 16 + (i - 6) * 8));
+                self.emit(&format!("mov {}, rax", slot(s)));
+            }
+        }
+
+        for (pos, block) in func.blocks.iter().enumerate() {
+            self.code += &format!("{}:\n", label(block.id));
+
+            for inst in &block.insts {
+                match inst {
+                    Inst::Const { dst, value } => {
+                        self.emit(&format!("mov rax, {}", value));
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::Binary {
+                        dst,
+                        op,
+                        left,
+                        right,
+                    } => {
+                        self.emit(&format!("mov rax, {}", reg(*left)));
+                        self.emit(&format!("mov rcx, {}", reg(*right)));
+                        match op {
+                            OperatorType::Addition => self.emit("add rax, rcx"),
+                            OperatorType::Subtraction => self.emit("sub rax, rcx"),
+                            OperatorType::Multiplication => self.emit("imul rax, rcx"),
+                            OperatorType::Division | OperatorType::Modulo => self.divide(*op),
+                            OperatorType::BitAnd => self.emit("and rax, rcx"),
+                            OperatorType::BitOr => self.emit("or rax, rcx"),
+                            OperatorType::BitXor => self.emit("xor rax, rcx"),
+                            // The cpu masks cl itself, see Inst::Binary
+                            OperatorType::ShiftLeft => self.emit("shl rax, cl"),
+                            OperatorType::ShiftRight => self.emit("sar rax, cl"),
+                            OperatorType::Equals
+                            | OperatorType::NotEquals
+                            | OperatorType::Less
+                            | OperatorType::Greater
+                            | OperatorType::LessEquals
+                            | OperatorType::GreaterEquals => {
+                                let set = match op {
+                                    OperatorType::Equals => "sete",
+                                    OperatorType::NotEquals => "setne",
+                                    OperatorType::Less => "setl",
+                                    OperatorType::Greater => "setg",
+                                    OperatorType::LessEquals => "setle",
+                                    OperatorType::GreaterEquals => "setge",
+                                    _ => unreachable!(),
+                                };
+                                self.emit("cmp rax, rcx");
+                                self.emit(&format!("{} al", set));
+                                self.emit("movzx eax, al");
+                            }
+                            // Never in the IR, see Inst::Binary
+                            OperatorType::And
+                            | OperatorType::Or
+                            | OperatorType::Not
+                            | OperatorType::Negate
+                            | OperatorType::BitNot => unreachable!(),
+                        }
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::CheckedBinary {
+                        dst,
+                        op,
+                        left,
+                        right,
+                        message,
+                    } => {
+                        let ok = self.new_label();
+                        self.emit(&format!("mov rax, {}", reg(*left)));
+                        self.emit(&format!("mov rcx, {}", reg(*right)));
+                        match op {
+                            OperatorType::Addition => self.emit("add rax, rcx"),
+                            OperatorType::Subtraction => self.emit("sub rax, rcx"),
+                            OperatorType::Multiplication => self.emit("imul rax, rcx"),
+                            // Division only overflows for the smallest int divided by -1
+                            _ => {
+                                self.emit("cmp rcx, -1");
+                                self.emit(&format!("jne {}", ok));
+                                self.emit(&format!("mov rdx, {}", i64::MIN));
+                                self.emit("cmp rax, rdx");
+                                self.emit(&format!("jne {}", ok));
+                            }
+                        }
+                        if matches!(
+                            op,
+                            OperatorType::Addition
+                                | OperatorType::Subtraction
+                                | OperatorType::Multiplication
+                        ) {
+                            self.emit(&format!("jno {}", ok));
+                        }
+                        self.fail("fail", message);
+                        self.place(&ok);
+                        if matches!(op, OperatorType::Division | OperatorType::Modulo) {
+                            self.divide(*op);
+                        }
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::Str { dst, value } => {
+                        self.strings.push(value.clone());
+                        self.emit(&format!("lea rax, [rip + .Lstr{}]", self.strings.len() - 1));
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::Load { dst, slot: s } => {
+                        self.emit(&format!("mov rax, {}", slot(*s)));
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::Store { slot: s, src } => {
+                        self.emit(&format!("mov rax, {}", reg(*src)));
+                        self.emit(&format!("mov {}, rax", slot(*s)));
+                    }
+                    Inst::AddrOf { dst, slot: s } => {
+                        self.emit(&format!("lea rax, {}", slot(*s)));
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::Index { dst, base, index } => {
+                        self.emit(&format!("mov rax, {}", reg(*base)));
+                        self.emit(&format!("mov rcx, {}", reg(*index)));
+                        self.emit("lea rax, [rax + rcx*8]");
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::LoadPtr { dst, ptr } => {
+                        self.emit(&format!("mov rax, {}", reg(*ptr)));
+                        self.emit("mov rax, qword ptr [rax]");
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::StorePtr { ptr, src } => {
+                        self.emit(&format!("mov rcx, {}", reg(*ptr)));
+                        self.emit(&format!("mov rax, {}", reg(*src)));
+                        self.emit("mov qword ptr [rcx], rax");
+                    }
+                    Inst::Call { dst, callee, args } => {
+                        // Arguments past the sixth go on the stack in reverse order, with
+                        // padding above them to keep rsp aligned at the call
+                        let stack_args = args.len().saturating_sub(ARG_REGS.len());
+                        let padding = stack_args % 2;
+                        if padding == 1 {
+                            self.emit("sub rsp, 8");
+                        }
+                        for &arg in args.iter().skip(ARG_REGS.len()).rev() {
+                            self.emit(&format!("push {}", reg(arg)));
+                        }
+                        for (&arg, r) in args.iter().zip(ARG_REGS) {
+                            self.emit(&format!("mov {}, {}", r, reg(arg)));
+                        }
+
+                        match callee {
+                            Callee::Function(name) => self.emit(&format!("call {}", symbol(name))),
+                            Callee::Print => self.emit("call print"),
+                            Callee::PrintStr => self.emit("call print_str"),
+                        }
+
+                        if stack_args + padding > 0 {
+                            self.emit(&format!("add rsp, {}", (stack_args + padding) * 8));
+                        }
+                        self.emit(&format!("mov {}, rax", reg(*dst)));
+                    }
+                    Inst::Check {
+                        cond,
+                        message,
+                        value,
+                    } => {
+                        let ok = self.new_label();
+                        self.emit(&format!("cmp {}, 0", reg(*cond)));
+                        self.emit(&format!("jne {}", ok));
+                        match value {
+                            Some(value) => {
+                                self.emit(&format!("mov rsi, {}", reg(*value)));
+                                self.fail("fail_value", message);
+                            }
+                            None => self.fail("fail", message),
+                        }
+                        self.place(&ok);
+                    }
+                    // Programs with structs are rejected before generating anything
+                    Inst::Field { .. } => unreachable!(),
+                    Inst::Line { .. } => {}
+                }
+            }
+
+            // Jumps to the block right after can fall through instead
+            let next = func.blocks.get(pos + 1).map(|b| b.id);
+            match &block.terminator {
+                Terminator::Jump(target) => {
+                    if next != Some(*target) {
+                        self.emit(&format!("jmp {}", label(*target)));
+                    }
+                }
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    self.emit(&format!("cmp {}, 0", reg(*cond)));
+                    self.emit(&format!("jne {}", label(*then)));
+                    if next != Some(*otherwise) {
+                        self.emit(&format!("jmp {}", label(*otherwise)));
+                    }
+                }
+                Terminator::Return(value) => {
+                    self.emit(&format!("mov rax, {}", reg(*value)));
+                    self.emit("leave");
+                    self.emit("ret");
+                }
+                Terminator::Unreachable => self.emit("ud2"),
+            }
+        }
+
+        self.code += "\n";
+        Ok(())
+    }
+}
+
+// Quotes a string for .asciz, anything but printable ASCII is written as octal escapes
+fn asm_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'"' => literal += "\\\"",
+            b'\\' => literal += "\\\\",
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal += &format!("\\{:03o}", byte),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+// Entry point, runs the top level statements and exits with whatever they return
+const START: &str = "_start:
+    call skl_main
+    mov edi, eax
+    mov eax, 60
+    syscall
+
+";
+
+// Writes the number in rdi and a newline to stdout, returns the number of bytes written.
+// write_int does the same for the file descriptor in esi
+const PRINT: &str = "print:
+    mov esi, 1
+write_int:
+    mov r9d, esi
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32
+    mov rax, rdi
+    mov r8, rdi
+    lea rsi, [rbp - 1]
+    mov byte ptr [rsi], 10
+    test rax, rax
+    jns .Lprint_digits
+    neg rax
+.Lprint_digits:
+    mov ecx, 10
+    xor edx, edx
+    div rcx
+    add dl, '0'
+    dec rsi
+    mov byte ptr [rsi], dl
+    test rax, rax
+    jnz .Lprint_digits
+    test r8, r8
+    jns .Lprint_write
+    dec rsi
+    mov byte ptr [rsi], '-'
+.Lprint_write:
+    mov rdx, rbp
+    sub rdx, rsi
+    mov edi, r9d
+    mov eax, 1
+    syscall
+    leave
+    ret
+
+";
+
+// Writes the NUL terminated string in rdi and a newline to stdout, returns the number of bytes
+// written
+const PRINT_STR: &str = "print_str:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 16
+    mov rsi, rdi
+    xor edx, edx
+.Lprint_str_len:
+    cmp byte ptr [rsi + rdx], 0
+    je .Lprint_str_write
+    inc rdx
+    jmp .Lprint_str_len
+.Lprint_str_write:
+    mov qword ptr [rbp - 8], rdx
+    mov edi, 1
+    mov eax, 1
+    syscall
+    mov byte ptr [rbp - 9], 10
+    lea rsi, [rbp - 9]
+    mov edx, 1
+    mov edi, 1
+    mov eax, 1
+    syscall
+    mov rax, qword ptr [rbp - 8]
+    inc rax
+    leave
+    ret
+
+";
+
+// Writes the NUL terminated message in rdi to stderr and exits with status 1, for failed checks.
+// fail_value also writes the number in rsi and a newline after the message. print writes
+// straight to stdout, so there is nothing to flush first
+const FAIL: &str = "fail:
+    call .Lfail_message
+    jmp .Lfail_exit
+fail_value:
+    mov rbx, rsi
+    call .Lfail_message
+    mov rdi, rbx
+    mov esi, 2
+    call write_int
+.Lfail_exit:
+    mov edi, 1
+    mov eax, 60
+    syscall
+.Lfail_message:
+    mov rsi, rdi
+    xor edx, edx
+.Lfail_len:
+    cmp byte ptr [rsi + rdx], 0
+    je .Lfail_write
+    inc rdx
+    jmp .Lfail_len
+.Lfail_write:
+    mov edi, 2
+    mov eax, 1
+    syscall
+    ret
+
+";
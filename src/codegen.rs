@@ -1,116 +1,358 @@
-use crate::{parser::*, OperatorType};
-
-pub struct CodeGenerator {
-    program: Program,
-    code: String,
-}
-
-impl CodeGenerator {
-    pub fn new(program: Program) -> Self {
-        Self {
-            program,
-            code: String::new(),
-        }
-    }
-
-    pub fn generate(&mut self) -> Result<String, String> {
-        self.code += "#include <stdio.h>\n";
-        self.code += "#define print(num) printf(\"%d\\n\", (num))\n";
-        self.code += "int _ = 0;\n";
-        self.code += "int main() {\n";
-        self.generate_stmts(self.program.statements.clone())?;
-        self.code += "}";
-
-        Ok(self.code.clone())
-    }
-
-    fn generate_stmts(&mut self, stmts: Vec<Stmt>) -> Result<(), String> {
-        for stmt in stmts {
-            match stmt {
-                Stmt::Assignment(ass_stmt) => {
-                    if ass_stmt.definition {
-                        self.code += "int "
-                    }
-                    self.code += &format!(
-                        "{} = {};\n",
-                        ass_stmt.name,
-                        self.generate_expr(ass_stmt.expr)?
-                    );
-                }
-                Stmt::Function(func_stmt) => {
-                    self.code += &format!("int {}(", func_stmt.name);
-                    for (i, param) in func_stmt.params.iter().enumerate() {
-                        self.code += &format!("int {}", param);
-                        if i < func_stmt.params.len() - 1 {
-                            self.code += ", ";
-                        }
-                    }
-                    self.code += ") {\n";
-
-                    self.generate_stmts(func_stmt.body)?;
-
-                    self.code += "}\n";
-                }
-                Stmt::If(if_stmt) => {
-                    self.code += &format!("if ({}) {{\n", self.generate_expr(if_stmt.condition)?);
-                    self.generate_stmts(if_stmt.body)?;
-                    self.code += "}";
-                    if !if_stmt.else_body.is_empty() {
-                        self.code += " else {\n";
-                        self.generate_stmts(if_stmt.else_body)?;
-                        self.code += "}";
-                    }
-                    self.code += "\n";
-                }
-                Stmt::Return(expr) => {
-                    if expr.is_none() {
-                        self.code += "return 0;\n";
-                    } else {
-                        self.code += &format!("return {};\n", self.generate_expr(expr.unwrap())?);
-                    }
-                }
-                Stmt::While(while_stmt) => {
-                    self.code +=
-                        &format!("while ({}) {{\n", self.generate_expr(while_stmt.condition)?);
-                    self.generate_stmts(while_stmt.body)?;
-                    self.code += "}\n";
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    fn generate_expr(&self, expr: Expr) -> Result<String, String> {
-        match expr {
-            Expr::Binary(bin_expr) => {
-                let left = self.generate_expr(bin_expr.left)?;
-                let right = self.generate_expr(bin_expr.right)?;
-
-                let op = match bin_expr.op {
-                    OperatorType::Addition => "+",
-                    OperatorType::Subtraction => "-",
-                    OperatorType::Multiplication => "*",
-                    OperatorType::Division => "/",
-                    OperatorType::Equals => "==",
-                    OperatorType::NotEquals => "!=",
-                };
-
-                Ok(format!("({} {} {})", left, op, right))
-            }
-            Expr::Call(name, params) => {
-                let mut code = format!("{}(", name);
-                for (i, param) in params.iter().enumerate() {
-                    code += &self.generate_expr(param.clone())?;
-                    if i < params.len() - 1 {
-                        code += ", ";
-                    }
-                }
-                code += ")";
-                Ok(code)
-            }
-            Expr::Number(num) => Ok(num.to_string()),
-            Expr::Variable(name) => Ok(name),
-        }
-    }
-}
+use crate::ir::*;
+use crate::resolve::{c_names, symbol};
+use crate::OperatorType;
+
+// Translates the IR to C. Slots become local variables named after them, registers become
+// locals named r{number} and blocks become labels, so the control flow is all gotos. Registers of
+// the same type share a local when their live ranges don't overlap, gcc keeps every local on the
+// stack without optimizations and deep recursion would run out of it. Every function is lifted to
+// the top level, with the top level statements going into main.
+
+pub struct CodeGenerator {
+    program: IrProgram,
+    code: String,
+    struct_names: Vec<String>,
+    field_names: Vec<Vec<String>>, // Per struct, in declaration order
+    locals: Vec<Option<usize>>,    // The local each register of the current function is in
+}
+
+impl CodeGenerator {
+    pub fn new(program: IrProgram) -> Self {
+        let struct_names = c_names(program.structs.iter().map(|decl| decl.name.as_str()));
+        let field_names = program
+            .structs
+            .iter()
+            .map(|decl| c_names(decl.fields.iter().map(|(name, _)| name.as_str())))
+            .collect();
+        Self {
+            program,
+            code: String::new(),
+            struct_names,
+            field_names,
+            locals: vec![],
+        }
+    }
+
+    pub fn generate(&mut self) -> String {
+        self.code += "#include <stdio.h>\n";
+        self.code += "#include <stdlib.h>\n";
+        self.code += "#include <inttypes.h>\n";
+        self.code += "#define print(num) printf(\"%\" PRId64 \"\\n\", (int64_t)(num))\n";
+        self.code += "#define print_str(str) printf(\"%s\\n\", (str))\n";
+        self.code += RUNTIME;
+        // Structs can only contain structs declared before them, so they are already in order
+        for (id, decl) in self.program.structs.iter().enumerate() {
+            self.code += &format!("struct {} {{\n", self.struct_names[id]);
+            for ((_, typ), name) in decl.fields.iter().zip(&self.field_names[id]) {
+                self.code += &format!("{};\n", self.declaration(*typ, name));
+            }
+            self.code += "};\n";
+        }
+
+        let functions = self.program.functions.clone();
+        for func in &functions[1..] {
+            self.code += &format!("{};\n", self.signature(func));
+        }
+        for func in &functions[1..] {
+            self.generate_function(func, self.signature(func));
+        }
+        self.generate_function(&functions[0], "int main(void)".to_string());
+
+        std::mem::take(&mut self.code)
+    }
+
+    // Params and captures are declared by the signature, the captures as pointers after the params
+    fn signature(&self, func: &IrFunction) -> String {
+        let params: Vec<String> = func
+            .params
+            .iter()
+            .chain(func.captures.iter())
+            .map(|&s| self.slot_declaration(func, s))
+            .collect();
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        self.declaration(func.ret, &format!("{}({})", symbol(&func.name), params))
+    }
+
+    // Bools are C ints since that's what comparisons give
+    fn c_type(&self, typ: IrType) -> String {
+        match typ {
+            IrType::Int => "int64_t".to_string(),
+            IrType::Bool => "int".to_string(),
+            IrType::Str => "const char *".to_string(),
+            IrType::Ptr => "void *".to_string(),
+            IrType::Struct(id) => format!("struct {}", self.struct_names[id]),
+        }
+    }
+
+    fn declaration(&self, typ: IrType, name: &str) -> String {
+        let c_type = self.c_type(typ);
+        if c_type.ends_with('*') {
+            format!("{}{}", c_type, name)
+        } else {
+            format!("{} {}", c_type, name)
+        }
+    }
+
+    fn slot_declaration(&self, func: &IrFunction, s: Slot) -> String {
+        match func.slot_types[s] {
+            SlotType::Value(typ) => self.declaration(typ, &func.slots[s]),
+            SlotType::Array(len) => format!("int64_t {}[{}]", func.slots[s], len),
+        }
+    }
+
+    fn generate_function(&mut self, func: &IrFunction, signature: String) {
+        self.code += &format!("{} {{\n", signature);
+        for s in 0..func.slots.len() {
+            if !func.params.contains(&s) && !func.captures.contains(&s) {
+                self.code += &format!("{};\n", self.slot_declaration(func, s));
+            }
+        }
+        let (locals, count) = func.share_registers(|typ| typ);
+        let mut types = vec![IrType::Int; count];
+        for (local, &typ) in locals.iter().zip(&func.regs) {
+            if let Some(local) = local {
+                types[*local] = typ;
+            }
+        }
+        for (local, typ) in types.into_iter().enumerate() {
+            self.code += &format!("{};\n", self.declaration(typ, &format!("r{}", local)));
+        }
+        self.locals = locals;
+
+        for (pos, block) in func.blocks.iter().enumerate() {
+            self.code += &format!("bb{}:\n", block.id);
+            for (i, inst) in block.insts.iter().enumerate() {
+                // Only the last of several markers in a row has anything after it
+                if matches!(inst, Inst::Line { .. })
+                    && matches!(block.insts.get(i + 1), Some(Inst::Line { .. }))
+                {
+                    continue;
+                }
+                self.generate_inst(func, inst);
+            }
+
+            // Jumps to the block right after can fall through instead
+            let next = func.blocks.get(pos + 1).map(|b| b.id);
+            match &block.terminator {
+                Terminator::Jump(target) => {
+                    if next != Some(*target) {
+                        self.code += &format!("goto bb{};\n", target);
+                    }
+                }
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    self.code += &format!("if ({}) goto bb{};\n", self.reg(*cond), then);
+                    if next != Some(*otherwise) {
+                        self.code += &format!("goto bb{};\n", otherwise);
+                    }
+                }
+                Terminator::Return(value) => {
+                    self.code += &format!("return {};\n", self.reg(*value))
+                }
+                Terminator::Unreachable => self.code += "__builtin_unreachable();\n",
+            }
+        }
+        self.code += "}\n";
+    }
+
+    fn reg(&self, r: Reg) -> String {
+        format!("r{}", self.locals[r].unwrap())
+    }
+
+    fn generate_inst(&mut self, func: &IrFunction, inst: &Inst) {
+        let line = match inst {
+            // A plain literal would be an int, which is too narrow for 1 shl 40. The smallest int
+            // has no literal in C, 9223372036854775808 doesn't fit before it is negated
+            Inst::Const { dst, value } => match func.regs[*dst] {
+                IrType::Int if *value == i64::MIN => format!("{} = INT64_MIN;", self.reg(*dst)),
+                IrType::Int => format!("{} = INT64_C({});", self.reg(*dst), value),
+                _ => format!("{} = {};", self.reg(*dst), value),
+            },
+            Inst::Str { dst, value } => format!("{} = {};", self.reg(*dst), c_string(value)),
+            Inst::Binary {
+                dst,
+                op,
+                left,
+                right,
+            } => {
+                let (left, right) = (self.reg(*left), self.reg(*right));
+                let value = match op {
+                    OperatorType::Division => format!("rt_div({}, {})", left, right),
+                    OperatorType::Modulo => format!("rt_rem({}, {})", left, right),
+                    // See Inst::Binary, C leaves shifting by 64 or more undefined
+                    OperatorType::ShiftLeft => format!("({} << ({} & 63))", left, right),
+                    OperatorType::ShiftRight => format!("({} >> ({} & 63))", left, right),
+                    _ => format!("({} {} {})", left, c_operator(*op), right),
+                };
+                format!("{} = {};", self.reg(*dst), value)
+            }
+            Inst::CheckedBinary {
+                dst,
+                op,
+                left,
+                right,
+                message,
+            } => {
+                let (dst, left, right) = (self.reg(*dst), self.reg(*left), self.reg(*right));
+                let message = c_string(message);
+                match op {
+                    OperatorType::Addition
+                    | OperatorType::Subtraction
+                    | OperatorType::Multiplication => {
+                        let builtin = match op {
+                            OperatorType::Addition => "add",
+                            OperatorType::Subtraction => "sub",
+                            _ => "mul",
+                        };
+                        format!(
+                            "if (__builtin_{}_overflow({}, {}, &{})) rt_fail({});",
+                            builtin, left, right, dst, message
+                        )
+                    }
+                    // Division only overflows for the smallest int divided by -1
+                    _ => {
+                        let function = match op {
+                            OperatorType::Division => "rt_div",
+                            _ => "rt_rem",
+                        };
+                        format!(
+                            "if ({} == INT64_MIN && {} == -1) rt_fail({});\n{} = {}({}, {});",
+                            left, right, message, dst, function, left, right
+                        )
+                    }
+                }
+            }
+            Inst::Load { dst, slot } => format!("{} = {};", self.reg(*dst), func.slots[*slot]),
+            Inst::Store { slot, src } => format!("{} = {};", func.slots[*slot], self.reg(*src)),
+            Inst::AddrOf { dst, slot } => format!("{} = &{};", self.reg(*dst), func.slots[*slot]),
+            Inst::Index { dst, base, index } => format!(
+                "{} = (int64_t *){} + {};",
+                self.reg(*dst),
+                self.reg(*base),
+                self.reg(*index)
+            ),
+            Inst::Field {
+                dst,
+                base,
+                id,
+                field,
+            } => format!(
+                "{} = &((struct {} *){})->{};",
+                self.reg(*dst),
+                self.struct_names[*id],
+                self.reg(*base),
+                self.field_names[*id][*field]
+            ),
+            Inst::LoadPtr { dst, ptr } => format!(
+                "{} = *({} *){};",
+                self.reg(*dst),
+                self.c_type(func.regs[*dst]),
+                self.reg(*ptr)
+            ),
+            Inst::StorePtr { ptr, src } => format!(
+                "*({} *){} = {};",
+                self.c_type(func.regs[*src]),
+                self.reg(*ptr),
+                self.reg(*src)
+            ),
+            Inst::Call { dst, callee, args } => {
+                let callee = match callee {
+                    Callee::Function(name) => symbol(name),
+                    Callee::Print => "print".to_string(),
+                    Callee::PrintStr => "print_str".to_string(),
+                };
+                let args: Vec<String> = args.iter().map(|&arg| self.reg(arg)).collect();
+                format!("{} = {}({});", self.reg(*dst), callee, args.join(", "))
+            }
+            Inst::Check {
+                cond,
+                message,
+                value,
+            } => match value {
+                Some(value) => format!(
+                    "if (!{}) rt_fail_value({}, {});",
+                    self.reg(*cond),
+                    c_string(message),
+                    self.reg(*value)
+                ),
+                None => format!("if (!{}) rt_fail({});", self.reg(*cond), c_string(message)),
+            },
+            // Lets gcc errors and debuggers point back at the .skl source
+            Inst::Line { file, line } => format!(
+                "#line {} \"{}\"",
+                line,
+                file.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+        };
+        self.code += &line;
+        self.code += "\n";
+    }
+}
+
+fn c_operator(op: OperatorType) -> &'static str {
+    match op {
+        OperatorType::Addition => "+",
+        OperatorType::Subtraction => "-",
+        OperatorType::Multiplication => "*",
+        OperatorType::BitAnd => "&",
+        OperatorType::BitOr => "|",
+        OperatorType::BitXor => "^",
+        OperatorType::Equals => "==",
+        OperatorType::NotEquals => "!=",
+        OperatorType::Less => "<",
+        OperatorType::Greater => ">",
+        OperatorType::LessEquals => "<=",
+        OperatorType::GreaterEquals => ">=",
+        // The caller writes out division, remainder and shifts, see Inst::Binary for the rest
+        _ => unreachable!(),
+    }
+}
+
+// Stops the program with a message from a failed check. Lowering checks for division by zero,
+// so division only has to let the smallest int divided by -1 wrap around like the other backends
+const RUNTIME: &str = "static inline void rt_fail(const char *message) {
+    fflush(stdout);
+    fputs(message, stderr);
+    exit(1);
+}
+static inline void rt_fail_value(const char *message, int64_t value) {
+    fflush(stdout);
+    fprintf(stderr, \"%s%\" PRId64 \"\\n\", message, value);
+    exit(1);
+}
+static inline int64_t rt_div(int64_t a, int64_t b) {
+    if (b == -1) return (int64_t)(0 - (uint64_t)a);
+    return a / b;
+}
+static inline int64_t rt_rem(int64_t a, int64_t b) {
+    if (b == -1) return 0;
+    return a % b;
+}
+";
+
+// Escapes a string for a C string literal. Anything that isn't printable ASCII is written as
+// octal escapes of its UTF-8 bytes, and ? is escaped so it can't form a trigraph
+fn c_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'"' => literal += "\\\"",
+            b'\\' => literal += "\\\\",
+            b'?' => literal += "\\?",
+            b'\n' => literal += "\\n",
+            b'\t' => literal += "\\t",
+            b'\r' => literal += "\\r",
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal += &format!("\\{:03o}", byte),
+        }
+    }
+    literal.push('"');
+    literal
+}
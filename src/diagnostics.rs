@@ -0,0 +1,191 @@
+use std::io::IsTerminal;
+
+use crate::lexer::Span;
+
+// Error codes are grouped by the stage that reports them:
+// E00xx lexer, E01xx parser, E02xx semantic analysis, E03xx code generation, E04xx driver,
+// E05xx runtime errors from the interpreter and the checks in the generated code.
+// Warnings use W and the same grouping, they don't stop the compilation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+impl Diagnostic {
+    fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    // The span that the diagnostic is about, underlined with ^
+    pub fn with_label(mut self, span: &Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span: span.clone(),
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    // Extra context such as a previous definition, underlined with -
+    pub fn with_secondary_label(mut self, span: &Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span: span.clone(),
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // Renders the diagnostic the way rustc does, with the offending source lines underlined
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut out = paint(self.severity.color(), self.severity.name());
+        out += &paint(self.severity.color(), &format!("[{}]", self.code));
+        out += &paint(BOLD, &format!(": {}", self.message));
+        out += "\n";
+
+        let mut labels = self.labels.clone();
+        labels.sort_by_key(|label| (label.span.line, label.span.col));
+
+        let gutter = labels
+            .iter()
+            .map(|label| label.span.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let pad = " ".repeat(gutter);
+
+        if let Some(first) = self.labels.iter().find(|l| l.primary).or(labels.first()) {
+            out += &format!("{}{} {}\n", pad, paint(BLUE, "-->"), first.span);
+            out += &format!("{} {}\n", pad, paint(BLUE, "|"));
+        }
+
+        let mut last_line = 0;
+        for label in &labels {
+            // Tabs are shown as single spaces so the columns line up with the underline
+            let line = source
+                .lines()
+                .nth(label.span.line - 1)
+                .unwrap_or("")
+                .replace('\t', " ");
+            let remaining = line
+                .chars()
+                .count()
+                .saturating_sub(label.span.col - 1)
+                .max(1);
+            let width = source
+                .get(label.span.start..label.span.end)
+                .map_or(1, |text| text.chars().count())
+                .clamp(1, remaining);
+
+            let (marker, style) = if label.primary {
+                ("^", self.severity.color())
+            } else {
+                ("-", BLUE)
+            };
+
+            // Labels on the same line share one copy of it
+            if label.span.line != last_line {
+                out += &format!(
+                    "{} {}\n",
+                    paint(BLUE, &format!("{:>w$} |", label.span.line, w = gutter)),
+                    line
+                );
+                last_line = label.span.line;
+            }
+            out += &format!(
+                "{} {}{}\n",
+                pad,
+                paint(BLUE, "|"),
+                paint(
+                    style,
+                    format!(
+                        " {}{} {}",
+                        " ".repeat(label.span.col - 1),
+                        marker.repeat(width),
+                        label.message
+                    )
+                    .trim_end()
+                )
+            );
+        }
+
+        for note in &self.notes {
+            out += &format!("{} {} note: {}\n", pad, paint(BLUE, "="), note);
+        }
+
+        out
+    }
+}
+
+// Prints the diagnostics to stderr, colored when stderr is a terminal
+pub fn emit(diagnostics: &[Diagnostic], source: &str) {
+    let color = std::io::stderr().is_terminal();
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(source, color));
+    }
+}
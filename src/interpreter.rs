@@ -0,0 +1,630 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::lexer::Span;
+use crate::resolve::*;
+use crate::types::*;
+use crate::{diagnostics::Diagnostic, parser::*, OperatorType};
+
+// Evaluates the AST directly. Variables are looked up through the same Resolution that the
+// backends use, so scoping and captures behave exactly like the generated code: every call gets
+// its own frame, and captured variables are shared cells just like the pointers in C.
+//
+// Every value is an i64. Strings can only be constants, so a string is the span start of its
+// literal, much like a pointer in the compiled code. An array is an index into arrays, every
+// time a definition runs it gets a new one, and it is freed when the block it was defined in is
+// done. A struct is an index into records, and it is copied whenever it is stored so that it
+// behaves like a value. Assigning to a struct overwrites its records instead, and the temporary
+// ones a statement makes along the way are freed once it is done.
+
+// How deeply calls can nest, about as deep as the compiled code gets with the default 8 MiB stack.
+// Every call recurses in the interpreter, main gives it a stack that is large enough for this
+const MAX_DEPTH: usize = 100_000;
+
+type Frame = HashMap<BindingId, Rc<Cell<i64>>>;
+
+enum Flow {
+    Normal,
+    Return(i64),
+    Break,
+    Continue,
+}
+
+pub struct Interpreter<'a> {
+    program: Program,
+    resolution: Resolution,
+    types: Types,
+    strings: HashMap<i64, String>, // String literals that have been evaluated
+    arrays: Vec<Vec<i64>>,
+    records: Vec<Vec<i64>>, // The fields of every struct value, in declaration order
+    functions: HashMap<FuncId, Rc<FunctionStmt>>,
+    discard: i64, // The global _ that call statements assign to
+    depth: usize,
+    overflow_checks: bool, // Arithmetic wraps around unless this is set
+    out: &'a mut dyn Write,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(
+        program: Program,
+        resolution: Resolution,
+        types: Types,
+        out: &'a mut dyn Write,
+    ) -> Self {
+        let mut functions = HashMap::new();
+        collect_functions(&program.statements, &resolution, &mut functions);
+
+        Self {
+            resolution,
+            types,
+            strings: HashMap::new(),
+            arrays: vec![],
+            records: vec![],
+            program,
+            functions,
+            discard: 0,
+            depth: 0,
+            overflow_checks: false,
+            out,
+        }
+    }
+
+    pub fn with_overflow_checks(mut self, overflow_checks: bool) -> Self {
+        self.overflow_checks = overflow_checks;
+        self
+    }
+
+    // Runs the program, returning what the top level returned like the exit code of main
+    pub fn run(&mut self) -> Result<i64, Diagnostic> {
+        let mut frame = Frame::new();
+        match self.exec_stmts(&self.program.statements.clone(), MAIN, &mut frame)? {
+            Flow::Return(value) => Ok(value),
+            Flow::Normal => Ok(0),
+            // Semantic analysis only allows yeet and bet inside loops
+            Flow::Break | Flow::Continue => unreachable!(),
+        }
+    }
+
+    // Variables are scoped to their block, so nothing can refer to the arrays and records made
+    // in it once it is done. A returned struct is kept until the call copies it out
+    fn exec_stmts(
+        &mut self,
+        stmts: &[Stmt],
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        let arrays = self.arrays.len();
+        let records = self.records.len();
+        let flow = self.exec_block(stmts, ctx, frame)?;
+        self.arrays.truncate(arrays);
+        if !matches!(flow, Flow::Return(_)) {
+            self.records.truncate(records);
+        }
+        Ok(flow)
+    }
+
+    fn exec_block(
+        &mut self,
+        stmts: &[Stmt],
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        // Every statement is run by a method of its own, which keeps the frames of the methods
+        // that recurse for every call small
+        for stmt in stmts {
+            let flow = match stmt {
+                Stmt::Assignment(ass_stmt) => self.exec_assignment(ass_stmt, ctx, frame),
+                Stmt::IndexAssignment(ass_stmt) => self.exec_index_assignment(ass_stmt, ctx, frame),
+                Stmt::FieldAssignment(ass_stmt) => self.exec_field_assignment(ass_stmt, ctx, frame),
+                Stmt::Function(_) => Ok(Flow::Normal),
+                Stmt::If(if_stmt) => self.exec_if(if_stmt, ctx, frame),
+                Stmt::Return(expr, _) => match expr.as_ref() {
+                    Some(expr) => self.eval(expr, ctx, frame).map(Flow::Return),
+                    None => Ok(Flow::Return(0)),
+                },
+                Stmt::While(while_stmt) => self.exec_while(while_stmt, ctx, frame),
+                Stmt::For(for_stmt) => self.exec_for(for_stmt, ctx, frame),
+                Stmt::Break(_) => Ok(Flow::Break),
+                Stmt::Continue(_) => Ok(Flow::Continue),
+            }?;
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn exec_assignment(
+        &mut self,
+        ass_stmt: &AssignmentStmt,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        let records = self.records.len();
+        let value = self.eval(&ass_stmt.expr, ctx, frame)?;
+        let var = self.resolution.variable(ctx, &ass_stmt.span);
+        let typ = self.types.binding(var.id);
+        match var.kind {
+            VarKind::Global => self.discard = value,
+            // A definition inside a loop gets a fresh variable every iteration
+            _ if ass_stmt.definition => {
+                let value = self.keep(records, value, typ);
+                frame.insert(var.id, Rc::new(Cell::new(value)));
+                return Ok(Flow::Normal);
+            }
+            _ => match typ {
+                Type::Struct(id) => self.assign(frame[&var.id].get(), value, id),
+                _ => frame[&var.id].set(value),
+            },
+        }
+        self.records.truncate(records);
+        Ok(Flow::Normal)
+    }
+
+    fn exec_index_assignment(
+        &mut self,
+        ass_stmt: &IndexAssignmentStmt,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        let records = self.records.len();
+        let (array, index) = self.element(&ass_stmt.span, &ass_stmt.index, ctx, frame)?;
+        let value = self.eval(&ass_stmt.expr, ctx, frame)?;
+        self.arrays[array][index] = value;
+        self.records.truncate(records);
+        Ok(Flow::Normal)
+    }
+
+    fn exec_field_assignment(
+        &mut self,
+        ass_stmt: &FieldAssignmentStmt,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        let records = self.records.len();
+        let (record, field) = self.field(&ass_stmt.target, ctx, frame)?;
+        let value = self.eval(&ass_stmt.expr, ctx, frame)?;
+        match self.types.of(&ass_stmt.expr) {
+            Type::Struct(id) => self.assign(self.records[record][field], value, id),
+            _ => self.records[record][field] = value,
+        }
+        self.records.truncate(records);
+        Ok(Flow::Normal)
+    }
+
+    fn exec_if(
+        &mut self,
+        if_stmt: &IfStmt,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        // The first branch whose condition holds runs, otherwise the sussy block
+        let mut body = &if_stmt.else_body;
+        for branch in &if_stmt.branches {
+            if self.test(&branch.condition, ctx, frame)? {
+                body = &branch.body;
+                break;
+            }
+        }
+        self.exec_stmts(body, ctx, frame)
+    }
+
+    fn exec_while(
+        &mut self,
+        while_stmt: &WhileStmt,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        while self.test(&while_stmt.condition, ctx, frame)? {
+            match self.exec_stmts(&while_stmt.body, ctx, frame)? {
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Break => break,
+                Flow::Normal | Flow::Continue => {}
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_for(
+        &mut self,
+        for_stmt: &ForStmt,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<Flow, Diagnostic> {
+        let start = self.eval(&for_stmt.start, ctx, frame)?;
+        let end = self.eval(&for_stmt.end, ctx, frame)?;
+        let var = self.resolution.variable(ctx, &for_stmt.var_span);
+        let counter = Rc::new(Cell::new(start));
+        frame.insert(var.id, counter.clone());
+
+        while counter.get() < end {
+            match self.exec_stmts(&for_stmt.body, ctx, frame)? {
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Break => break,
+                Flow::Normal | Flow::Continue => {}
+            }
+            counter.set(counter.get().wrapping_add(1));
+        }
+        Ok(Flow::Normal)
+    }
+
+    // The array at span and the index into it, which has to be in bounds
+    fn element(
+        &mut self,
+        span: &Span,
+        index: &Expr,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<(usize, usize), Diagnostic> {
+        let var = self.resolution.variable(ctx, span);
+        let array = frame[&var.id].get() as usize;
+        let value = self.eval(index, ctx, frame)?;
+        let len = self.arrays[array].len();
+        if !(0..len as i64).contains(&value) {
+            return Err(
+                Diagnostic::error("E0502", "index out of bounds").with_label(
+                    index.span(),
+                    format!("the length is {} but the index is {}", len, value),
+                ),
+            );
+        }
+        Ok((array, value as usize))
+    }
+
+    // The wrapped result of an overflowing_* operation, which is an error with overflow checks
+    fn arithmetic(
+        &self,
+        (value, overflowed): (i64, bool),
+        what: &str,
+        span: &Span,
+    ) -> Result<i64, Diagnostic> {
+        if overflowed && self.overflow_checks {
+            return Err(
+                Diagnostic::error("E0503", format!("attempt to {} with overflow", what))
+                    .with_label(span, "the result doesn't fit in an int"),
+            );
+        }
+        Ok(value)
+    }
+
+    // The record that holds the field and the index of the field in it
+    fn field(
+        &mut self,
+        field: &FieldExpr,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<(usize, usize), Diagnostic> {
+        let Type::Struct(id) = self.types.of(&field.expr) else {
+            unreachable!()
+        };
+        let record = self.eval(&field.expr, ctx, frame)? as usize;
+        Ok((
+            record,
+            self.program.structs[id].field(&field.field).unwrap(),
+        ))
+    }
+
+    // Evaluates a condition, any records it makes are temporaries
+    fn test(&mut self, cond: &Expr, ctx: FuncId, frame: &mut Frame) -> Result<bool, Diagnostic> {
+        let records = self.records.len();
+        let value = self.eval(cond, ctx, frame)?;
+        self.records.truncate(records);
+        Ok(value != 0)
+    }
+
+    fn field_types(&self, id: StructId) -> Vec<Type> {
+        self.program.structs[id]
+            .fields
+            .iter()
+            .map(|field| field.typ)
+            .collect()
+    }
+
+    // A struct gets a new record with copies of its fields, other values are returned as is
+    fn copy(&mut self, value: i64, typ: Type) -> i64 {
+        self.keep(self.records.len(), value, typ)
+    }
+
+    // Frees every record from mark on and returns a copy of value, which may have been one of them
+    fn keep(&mut self, mark: usize, value: i64, typ: Type) -> i64 {
+        let freed = self.records.split_off(mark);
+        self.rebuild(&freed, mark, value, typ)
+    }
+
+    fn rebuild(&mut self, freed: &[Vec<i64>], mark: usize, value: i64, typ: Type) -> i64 {
+        let Type::Struct(id) = typ else {
+            return value;
+        };
+        let record = value as usize;
+        let mut fields = match record.checked_sub(mark) {
+            Some(i) => freed[i].clone(),
+            None => self.records[record].clone(),
+        };
+        for (i, typ) in self.field_types(id).into_iter().enumerate() {
+            fields[i] = self.rebuild(freed, mark, fields[i], typ);
+        }
+        self.records.push(fields);
+        self.records.len() as i64 - 1
+    }
+
+    // Overwrites the record of a struct with the fields of value, nested structs included, so
+    // that assigning doesn't need any new records
+    fn assign(&mut self, record: i64, value: i64, id: StructId) {
+        let (record, value) = (record as usize, value as usize);
+        for (i, typ) in self.field_types(id).into_iter().enumerate() {
+            let field = self.records[value][i];
+            match typ {
+                Type::Struct(inner) => self.assign(self.records[record][i], field, inner),
+                _ => self.records[record][i] = field,
+            }
+        }
+    }
+
+    // Larger expressions have methods of their own for the same reason as statements
+    fn eval(&mut self, expr: &Expr, ctx: FuncId, frame: &mut Frame) -> Result<i64, Diagnostic> {
+        match expr {
+            Expr::Binary(bin_expr) => self.eval_binary(bin_expr, ctx, frame),
+            Expr::Unary(unary_expr) => self.eval_unary(unary_expr, ctx, frame),
+            Expr::Array(array) => self.eval_array(array, ctx, frame),
+            Expr::Index(index) => self
+                .element(&index.span, &index.index, ctx, frame)
+                .map(|(array, index)| self.arrays[array][index]),
+            Expr::Struct(struct_expr) => self.eval_struct(struct_expr, ctx, frame),
+            Expr::Field(field) => self
+                .field(field, ctx, frame)
+                .map(|(record, field)| self.records[record][field]),
+            Expr::Call(name, params, span)
+                if name == "len" && self.resolution.callee(span).is_none() =>
+            {
+                self.eval(&params[0], ctx, frame)
+                    .map(|array| self.arrays[array as usize].len() as i64)
+            }
+            Expr::Call(_, params, span) => self.eval_call(params, span, ctx, frame),
+            Expr::Number(num, _) => Ok(*num),
+            Expr::Str(value, span) => {
+                let id = span.start as i64;
+                self.strings.entry(id).or_insert_with(|| value.clone());
+                Ok(id)
+            }
+            Expr::Bool(value, _) => Ok(*value as i64),
+            Expr::Variable(_, span) => {
+                let var = self.resolution.variable(ctx, span);
+                match var.kind {
+                    VarKind::Global => Ok(self.discard),
+                    _ => Ok(frame[&var.id].get()),
+                }
+            }
+        }
+    }
+
+    fn eval_binary(
+        &mut self,
+        bin_expr: &BinaryExpr,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<i64, Diagnostic> {
+        let left = self.eval(&bin_expr.left, ctx, frame)?;
+        // The right side of and/or is only evaluated if the left side doesn't decide
+        match bin_expr.op {
+            OperatorType::And if left == 0 => return Ok(0),
+            OperatorType::Or if left != 0 => return Ok(1),
+            _ => {}
+        }
+        let right = self.eval(&bin_expr.right, ctx, frame)?;
+
+        self.binary(bin_expr.op, left, right, &bin_expr.span)
+    }
+
+    fn binary(
+        &self,
+        op: OperatorType,
+        left: i64,
+        right: i64,
+        span: &Span,
+    ) -> Result<i64, Diagnostic> {
+        Ok(match op {
+            OperatorType::Addition => self.arithmetic(left.overflowing_add(right), "add", span)?,
+            OperatorType::Subtraction => {
+                self.arithmetic(left.overflowing_sub(right), "subtract", span)?
+            }
+            OperatorType::Multiplication => {
+                self.arithmetic(left.overflowing_mul(right), "multiply", span)?
+            }
+            OperatorType::Division | OperatorType::Modulo => {
+                if right == 0 {
+                    return Err(Diagnostic::error("E0500", "attempt to divide by zero")
+                        .with_label(span, "the right side is zero"));
+                }
+                match op {
+                    OperatorType::Division => {
+                        self.arithmetic(left.overflowing_div(right), "divide", span)?
+                    }
+                    _ => self.arithmetic(
+                        left.overflowing_rem(right),
+                        "calculate the remainder",
+                        span,
+                    )?,
+                }
+            }
+            OperatorType::BitAnd => left & right,
+            OperatorType::BitOr => left | right,
+            OperatorType::BitXor => left ^ right,
+            // wrapping_shl only uses the low six bits, like the compiled backends
+            OperatorType::ShiftLeft => left.wrapping_shl(right as u32),
+            OperatorType::ShiftRight => left.wrapping_shr(right as u32),
+            OperatorType::Equals => (left == right) as i64,
+            OperatorType::NotEquals => (left != right) as i64,
+            OperatorType::Less => (left < right) as i64,
+            OperatorType::Greater => (left > right) as i64,
+            OperatorType::LessEquals => (left <= right) as i64,
+            OperatorType::GreaterEquals => (left >= right) as i64,
+            OperatorType::And | OperatorType::Or => right,
+            OperatorType::Not | OperatorType::Negate | OperatorType::BitNot => {
+                unreachable!()
+            }
+        })
+    }
+
+    fn eval_unary(
+        &mut self,
+        unary_expr: &UnaryExpr,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<i64, Diagnostic> {
+        let value = self.eval(&unary_expr.expr, ctx, frame)?;
+        match unary_expr.op {
+            OperatorType::Not => Ok((value == 0) as i64),
+            OperatorType::Negate => {
+                self.arithmetic(value.overflowing_neg(), "negate", &unary_expr.span)
+            }
+            OperatorType::BitNot => Ok(!value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn eval_array(
+        &mut self,
+        array: &ArrayExpr,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<i64, Diagnostic> {
+        let mut values = vec![];
+        for element in &array.elements {
+            values.push(self.eval(element, ctx, frame)?);
+        }
+        if let Some(len) = array.repeat {
+            values = vec![values[0]; len];
+        }
+        self.arrays.push(values);
+        Ok(self.arrays.len() as i64 - 1)
+    }
+
+    fn eval_struct(
+        &mut self,
+        struct_expr: &StructExpr,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<i64, Diagnostic> {
+        let decl = &self.program.structs[struct_expr.id];
+        let mut fields = vec![0; decl.fields.len()];
+        for init in &struct_expr.fields {
+            let i = self.program.structs[struct_expr.id]
+                .field(&init.name)
+                .unwrap();
+            let value = self.eval(&init.expr, ctx, frame)?;
+            fields[i] = self.copy(value, self.types.of(&init.expr));
+        }
+        self.records.push(fields);
+        Ok(self.records.len() as i64 - 1)
+    }
+
+    fn eval_call(
+        &mut self,
+        params: &[Expr],
+        span: &Span,
+        ctx: FuncId,
+        frame: &mut Frame,
+    ) -> Result<i64, Diagnostic> {
+        let mut args = vec![];
+        for param in params {
+            args.push(self.eval(param, ctx, frame)?);
+        }
+
+        match self.resolution.callee(span) {
+            Some(id) => self.call(id, args, frame, span),
+            None => {
+                // print is the only built-in, it returns the number of bytes written like printf
+                let text = match self.types.of(&params[0]) {
+                    Type::Str => format!("{}\n", self.strings[&args[0]]),
+                    _ => format!("{}\n", args[0]),
+                };
+                let _ = self.out.write_all(text.as_bytes());
+                Ok(text.len() as i64)
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        id: FuncId,
+        args: Vec<i64>,
+        frame: &Frame,
+        span: &Span,
+    ) -> Result<i64, Diagnostic> {
+        if self.depth == MAX_DEPTH {
+            return Err(too_deep(span));
+        }
+
+        let records = self.records.len();
+        let mut callee_frame = self.callee_frame(id, args, frame);
+        let func = self.functions[&id].clone();
+        self.depth += 1;
+        let flow = self.exec_stmts(&func.body, id, &mut callee_frame);
+        self.depth -= 1;
+
+        // Everything the call made is freed, apart from a returned struct
+        match flow? {
+            Flow::Return(value) => Ok(self.keep(records, value, self.types.returns(id))),
+            Flow::Normal => {
+                self.records.truncate(records);
+                Ok(0)
+            }
+            // As in run()
+            Flow::Break | Flow::Continue => unreachable!(),
+        }
+    }
+
+    // The frame a call starts with, holding copies of the arguments
+    fn callee_frame(&mut self, id: FuncId, args: Vec<i64>, frame: &Frame) -> Frame {
+        let params = self.resolution.info(id).params.clone();
+        let mut callee_frame = Frame::new();
+        for (param, value) in params.into_iter().zip(args) {
+            let value = self.copy(value, self.types.binding(param));
+            callee_frame.insert(param, Rc::new(Cell::new(value)));
+        }
+        let info = self.resolution.info(id);
+        // Captured variables share the caller's cell
+        for &binding in &info.captures {
+            callee_frame.insert(binding, frame[&binding].clone());
+        }
+        callee_frame
+    }
+}
+
+fn too_deep(span: &Span) -> Diagnostic {
+    Diagnostic::error("E0501", "stack overflow")
+        .with_label(span, "too many nested calls")
+        .with_note(format!("calls can only nest {} deep", MAX_DEPTH))
+}
+
+fn collect_functions(
+    stmts: &[Stmt],
+    resolution: &Resolution,
+    functions: &mut HashMap<FuncId, Rc<FunctionStmt>>,
+) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Function(func) => {
+                functions.insert(resolution.function(func), Rc::new(func.clone()));
+                collect_functions(&func.body, resolution, functions);
+            }
+            Stmt::If(if_stmt) => {
+                for branch in &if_stmt.branches {
+                    collect_functions(&branch.body, resolution, functions);
+                }
+                collect_functions(&if_stmt.else_body, resolution, functions);
+            }
+            Stmt::While(while_stmt) => collect_functions(&while_stmt.body, resolution, functions),
+            Stmt::For(for_stmt) => collect_functions(&for_stmt.body, resolution, functions),
+            Stmt::Assignment(_)
+            | Stmt::IndexAssignment(_)
+            | Stmt::FieldAssignment(_)
+            | Stmt::Return(..)
+            | Stmt::Break(_)
+            | Stmt::Continue(_) => {}
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+
+use crate::lexer::Span;
+use crate::types::StructId;
+use crate::OperatorType;
+
+// A three-address IR between the AST and the backends. Every function is a list of basic
+// blocks that end in an explicit terminator, and values live in virtual registers that are
+// assigned exactly once. Variables are not registers, they live in stack slots that are read
+// and written with Load and Store, which keeps lowering simple without needing phi nodes.
+// The optimization passes and both compiled backends work on it.
+
+pub type Reg = usize;
+pub type BlockId = usize;
+pub type Slot = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IrType {
+    Int,
+    Bool,
+    Str, // Pointer to a NUL terminated string constant
+    // The address of a slot, array element or field, used for captured variables and arrays
+    Ptr,
+    Struct(StructId), // A whole struct, copied around by value
+}
+
+// What a slot holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlotType {
+    Value(IrType),
+    Array(usize), // Fixed length array of ints
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Function(String),
+    Print,
+    PrintStr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Const {
+        dst: Reg,
+        value: i64,
+    },
+    Str {
+        dst: Reg,
+        value: String,
+    },
+    // Never and, or or the unary operators, lowering turns those into branches, comparisons,
+    // subtraction and xor. Shifts only use the low six bits of the amount
+    Binary {
+        dst: Reg,
+        op: OperatorType,
+        left: Reg,
+        right: Reg,
+    },
+    // Like Binary for the operators that can overflow, but stops the program with message when
+    // the result doesn't fit in an int
+    CheckedBinary {
+        dst: Reg,
+        op: OperatorType,
+        left: Reg,
+        right: Reg,
+        message: String,
+    },
+    Load {
+        dst: Reg,
+        slot: Slot,
+    },
+    Store {
+        slot: Slot,
+        src: Reg,
+    },
+    AddrOf {
+        dst: Reg,
+        slot: Slot,
+    },
+    // The address of element index of the array that base points to
+    Index {
+        dst: Reg,
+        base: Reg,
+        index: Reg,
+    },
+    // The address of field number field of the struct id that base points to
+    Field {
+        dst: Reg,
+        base: Reg,
+        id: StructId,
+        field: usize,
+    },
+    LoadPtr {
+        dst: Reg,
+        ptr: Reg,
+    },
+    StorePtr {
+        ptr: Reg,
+        src: Reg,
+    },
+    Call {
+        dst: Reg,
+        callee: Callee,
+        args: Vec<Reg>,
+    },
+    // Stops the program unless cond holds, printing message and then value if there is one to
+    // stderr and exiting with 1
+    Check {
+        cond: Reg,
+        message: String,
+        value: Option<Reg>,
+    },
+    // Where in the source the following instructions come from, doesn't do anything
+    Line {
+        file: String,
+        line: usize,
+    },
+}
+
+impl Inst {
+    // The register the instruction defines, if any
+    pub fn dst(&self) -> Option<Reg> {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Str { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::CheckedBinary { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::AddrOf { dst, .. }
+            | Inst::Index { dst, .. }
+            | Inst::Field { dst, .. }
+            | Inst::LoadPtr { dst, .. }
+            | Inst::Call { dst, .. } => Some(*dst),
+            Inst::Store { .. } | Inst::StorePtr { .. } | Inst::Check { .. } | Inst::Line { .. } => {
+                None
+            }
+        }
+    }
+
+    // The registers the instruction reads
+    pub fn uses(&self) -> Vec<Reg> {
+        match self {
+            Inst::Const { .. }
+            | Inst::Str { .. }
+            | Inst::Load { .. }
+            | Inst::AddrOf { .. }
+            | Inst::Line { .. } => vec![],
+            Inst::Binary { left, right, .. } | Inst::CheckedBinary { left, right, .. } => {
+                vec![*left, *right]
+            }
+            Inst::Store { src, .. } => vec![*src],
+            Inst::Index { base, index, .. } => vec![*base, *index],
+            Inst::Field { base, .. } => vec![*base],
+            Inst::LoadPtr { ptr, .. } => vec![*ptr],
+            Inst::StorePtr { ptr, src } => vec![*ptr, *src],
+            Inst::Call { args, .. } => args.clone(),
+            Inst::Check { cond, value, .. } => std::iter::once(*cond).chain(*value).collect(),
+        }
+    }
+
+    // Whether the instruction does nothing but compute its value, so it can go if that isn't used
+    pub fn is_pure(&self) -> bool {
+        matches!(
+            self,
+            Inst::Const { .. }
+                | Inst::Str { .. }
+                | Inst::Binary { .. }
+                | Inst::Load { .. }
+                | Inst::AddrOf { .. }
+                | Inst::Index { .. }
+                | Inst::Field { .. }
+                | Inst::LoadPtr { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        cond: Reg,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return(Reg),
+    // The end of a block that never runs to its end, like the exit of an edge nocap that is only
+    // left through sigma
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn uses(&self) -> Vec<Reg> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Return(value) => vec![*value],
+            Terminator::Jump(_) | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub id: BlockId,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone)]
+pub struct IrFunction {
+    pub name: String,
+    pub params: Vec<Slot>,   // Slots the arguments are stored in on entry
+    pub captures: Vec<Slot>, // Slots holding pointers to captured variables, passed after the params
+    pub slots: Vec<String>,  // Name of every slot, unique inside the function
+    pub slot_types: Vec<SlotType>,
+    pub regs: Vec<IrType>,  // Type of every virtual register
+    pub ret: IrType,        // Type of the returned value
+    pub blocks: Vec<Block>, // The first block is the entry
+}
+
+impl IrFunction {
+    // Gives every register a word so that registers can share one, and returns how many words
+    // that takes. Registers of the same class share a word when one of them is dead before the
+    // other is defined, and registers that nothing uses anymore get none. Registers are assigned
+    // once, so one is live from its definition to its last use, widened to the whole of every
+    // block it is live through
+    pub fn share_registers<K: Clone + Eq + Hash>(
+        &self,
+        class: impl Fn(IrType) -> K,
+    ) -> (Vec<Option<usize>>, usize) {
+        // Registers read in a block before anything in it defines them, and the ones it defines
+        let mut gen: Vec<HashSet<Reg>> = vec![];
+        let mut defs: Vec<HashSet<Reg>> = vec![];
+        for block in &self.blocks {
+            let mut block_gen = HashSet::new();
+            let mut block_defs = HashSet::new();
+            for inst in &block.insts {
+                block_gen.extend(inst.uses().into_iter().filter(|r| !block_defs.contains(r)));
+                block_defs.extend(inst.dst());
+            }
+            block_gen.extend(
+                block
+                    .terminator
+                    .uses()
+                    .into_iter()
+                    .filter(|r| !block_defs.contains(r)),
+            );
+            gen.push(block_gen);
+            defs.push(block_defs);
+        }
+
+        let position: HashMap<BlockId, usize> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.id, i))
+            .collect();
+        let mut live_in: Vec<HashSet<Reg>> = vec![HashSet::new(); self.blocks.len()];
+        let mut live_out: Vec<HashSet<Reg>> = vec![HashSet::new(); self.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, block) in self.blocks.iter().enumerate().rev() {
+                let out: HashSet<Reg> = block
+                    .terminator
+                    .successors()
+                    .iter()
+                    .flat_map(|id| live_in[position[id]].iter().copied())
+                    .collect();
+                let mut live: HashSet<Reg> = out.difference(&defs[i]).copied().collect();
+                live.extend(&gen[i]);
+                if live != live_in[i] || out != live_out[i] {
+                    live_in[i] = live;
+                    live_out[i] = out;
+                    changed = true;
+                }
+            }
+        }
+
+        // Numbers the instructions in order, with the terminator last in every block
+        let mut ranges: Vec<Option<(usize, usize)>> = vec![None; self.regs.len()];
+        let mut widen = |r: Reg, at: usize| {
+            let range = ranges[r].get_or_insert((at, at));
+            *range = (range.0.min(at), range.1.max(at));
+        };
+        let mut at = 0;
+        for (i, block) in self.blocks.iter().enumerate() {
+            let start = at;
+            for inst in &block.insts {
+                for r in inst.uses().into_iter().chain(inst.dst()) {
+                    widen(r, at);
+                }
+                at += 1;
+            }
+            for r in block.terminator.uses() {
+                widen(r, at);
+            }
+            for &r in &live_in[i] {
+                widen(r, start);
+            }
+            for &r in &live_out[i] {
+                widen(r, at);
+            }
+            at += 1;
+        }
+
+        let mut order: Vec<Reg> = (0..self.regs.len())
+            .filter(|&r| ranges[r].is_some())
+            .collect();
+        order.sort_by_key(|&r| ranges[r].unwrap().0);
+        let mut words = vec![None; self.regs.len()];
+        let mut count = 0;
+        let mut free: HashMap<K, Vec<usize>> = HashMap::new();
+        let mut active: Vec<(usize, usize, K)> = vec![]; // The end of a range, its word and class
+        for r in order {
+            let (start, end) = ranges[r].unwrap();
+            active.retain(|(active_end, word, class)| {
+                if *active_end < start {
+                    free.entry(class.clone()).or_default().push(*word);
+                }
+                *active_end >= start
+            });
+            let class = class(self.regs[r]);
+            let word = free
+                .entry(class.clone())
+                .or_default()
+                .pop()
+                .unwrap_or_else(|| {
+                    count += 1;
+                    count - 1
+                });
+            words[r] = Some(word);
+            active.push((end, word, class));
+        }
+        (words, count)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IrStruct {
+    pub name: String,
+    pub fields: Vec<(String, IrType)>, // In declaration order
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct IrProgram {
+    pub structs: Vec<IrStruct>, // Indexed by StructId, fields only use structs before them
+    pub functions: Vec<IrFunction>, // main comes first
+}
+
+fn op_name(op: OperatorType) -> &'static str {
+    match op {
+        OperatorType::Addition => "add",
+        OperatorType::Subtraction => "sub",
+        OperatorType::Multiplication => "mul",
+        OperatorType::Division => "div",
+        OperatorType::Modulo => "mod",
+        OperatorType::BitAnd => "and",
+        OperatorType::BitOr => "or",
+        OperatorType::BitXor => "xor",
+        OperatorType::ShiftLeft => "shl",
+        OperatorType::ShiftRight => "shr",
+        OperatorType::Equals => "eq",
+        OperatorType::NotEquals => "ne",
+        OperatorType::Less => "lt",
+        OperatorType::Greater => "gt",
+        OperatorType::LessEquals => "le",
+        OperatorType::GreaterEquals => "ge",
+        // Never in the IR, see Inst::Binary
+        OperatorType::And
+        | OperatorType::Or
+        | OperatorType::Not
+        | OperatorType::Negate
+        | OperatorType::BitNot => unreachable!(),
+    }
+}
+
+impl fmt::Display for IrType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrType::Int => write!(f, "int"),
+            IrType::Bool => write!(f, "bool"),
+            IrType::Str => write!(f, "str"),
+            IrType::Ptr => write!(f, "ptr"),
+            IrType::Struct(id) => write!(f, "struct{}", id),
+        }
+    }
+}
+
+impl fmt::Display for IrFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slot = |s: &Slot| format!("${}.{}", s, self.slots[*s]);
+        let reg = |r: &Reg| format!("%{}", r);
+        let list = |slots: &[Slot]| slots.iter().map(slot).collect::<Vec<_>>().join(", ");
+
+        writeln!(
+            f,
+            "fn {}({}) captures({}) -> {} {{",
+            self.name,
+            list(&self.params),
+            list(&self.captures),
+            self.ret
+        )?;
+        for block in &self.blocks {
+            writeln!(f, "bb{}:", block.id)?;
+            for inst in &block.insts {
+                let line = match inst {
+                    Inst::Const { dst, value } => format!("{} = const {}", reg(dst), value),
+                    Inst::Str { dst, value } => format!("{} = str {:?}", reg(dst), value),
+                    Inst::Binary {
+                        dst,
+                        op,
+                        left,
+                        right,
+                    } => format!(
+                        "{} = {} {}, {}",
+                        reg(dst),
+                        op_name(*op),
+                        reg(left),
+                        reg(right)
+                    ),
+                    Inst::CheckedBinary {
+                        dst,
+                        op,
+                        left,
+                        right,
+                        message,
+                    } => format!(
+                        "{} = {}.checked {}, {}, {:?}",
+                        reg(dst),
+                        op_name(*op),
+                        reg(left),
+                        reg(right),
+                        message
+                    ),
+                    Inst::Load { dst, slot: s } => format!("{} = load {}", reg(dst), slot(s)),
+                    Inst::Store { slot: s, src } => format!("store {}, {}", slot(s), reg(src)),
+                    Inst::AddrOf { dst, slot: s } => format!("{} = addr {}", reg(dst), slot(s)),
+                    Inst::Index { dst, base, index } => {
+                        format!("{} = index {}, {}", reg(dst), reg(base), reg(index))
+                    }
+                    Inst::Field {
+                        dst,
+                        base,
+                        id,
+                        field,
+                    } => format!("{} = field {}, struct{}.{}", reg(dst), reg(base), id, field),
+                    Inst::LoadPtr { dst, ptr } => format!("{} = load [{}]", reg(dst), reg(ptr)),
+                    Inst::StorePtr { ptr, src } => format!("store [{}], {}", reg(ptr), reg(src)),
+                    Inst::Call { dst, callee, args } => format!(
+                        "{} = call {}({})",
+                        reg(dst),
+                        match callee {
+                            Callee::Function(name) => name.as_str(),
+                            Callee::Print => "@print",
+                            Callee::PrintStr => "@print_str",
+                        },
+                        args.iter().map(reg).collect::<Vec<_>>().join(", ")
+                    ),
+                    Inst::Check {
+                        cond,
+                        message,
+                        value,
+                    } => match value {
+                        Some(value) => {
+                            format!("check {}, {:?}, {}", reg(cond), message, reg(value))
+                        }
+                        None => format!("check {}, {:?}", reg(cond), message),
+                    },
+                    Inst::Line { file, line } => format!("line {}:{}", file, line),
+                };
+                match inst.dst() {
+                    Some(dst) => writeln!(f, "    {:<32} ; {}", line, self.regs[dst])?,
+                    None => writeln!(f, "    {}", line)?,
+                }
+            }
+            let line = match &block.terminator {
+                Terminator::Jump(target) => format!("jmp bb{}", target),
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => format!("br {}, bb{}, bb{}", reg(cond), then, otherwise),
+                Terminator::Return(value) => format!("ret {}", reg(value)),
+                Terminator::Unreachable => "unreachable".to_string(),
+            };
+            writeln!(f, "    {}", line)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, decl) in self.structs.iter().enumerate() {
+            let fields: Vec<String> = decl
+                .fields
+                .iter()
+                .map(|(name, typ)| format!("{}: {}", name, typ))
+                .collect();
+            writeln!(f, "struct{} {} {{ {} }}", id, decl.name, fields.join(", "))?;
+        }
+        if !self.structs.is_empty() {
+            writeln!(f)?;
+        }
+        for (i, func) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,8 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::diagnostics::Diagnostic;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KeywordType {
     If,
@@ -5,6 +10,10 @@ pub enum KeywordType {
     While,
     Function,
     VarDef,
+    Break,
+    Continue,
+    In,
+    Struct,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -14,36 +23,194 @@ pub enum OperatorType {
     Subtraction,
     Multiplication,
     Division,
+    Modulo,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Equals,
     NotEquals,
+    Less,
+    Greater,
+    LessEquals,
+    GreaterEquals,
+    And,
+    Or,
+    // Prefix operators, the others are binary
+    Not,
+    Negate,
+    BitNot,
+}
+
+impl OperatorType {
+    // Operators that produce a bool
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            OperatorType::Equals
+                | OperatorType::NotEquals
+                | OperatorType::Less
+                | OperatorType::Greater
+                | OperatorType::LessEquals
+                | OperatorType::GreaterEquals
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 
 pub enum TokenType {
     Identifier(String),
-    Integer(i64),
+    Integer(u64),   // The magnitude, a minus in front is an operator
+    String(String), // With the escape sequences already replaced
+    Boolean(bool),
     Keyword(KeywordType),
     Operator(OperatorType),
     LeftParan,
     RightParan,
+    LeftSquare,
+    RightSquare,
+    Semicolon,
     LeftBrack,
     RightBrack,
     Assignment,
     Return,
     Comma,
-    Pipe, // Semicolon type shit
+    Colon,
+    Dot,
+    DotDot,
+    Pipe,            // Semicolon type shit
+    Comment(String), // Trivia, the parser skips these but a formatter needs them
+    Eof,
+}
+
+impl TokenType {
+    // Tokens that don't affect the meaning of the program
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenType::Comment(_))
+    }
+}
+
+// Where something is in the source, start and end are byte offsets and line/col are 1-based,
+// with col counted in characters so that carets line up under text that isn't ASCII
+#[derive(Clone, PartialEq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    // Returns a span covering both self and other, self has to come first
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+// The derived Debug would print the whole struct for every node in the AST dump
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}..{}]", self, self.start, self.end)
+    }
+}
+
+// How the token is spelled in the source, used in error messages
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenType::Identifier(name) if name.is_empty() => write!(f, "identifier"),
+            TokenType::Identifier(name) => write!(f, "identifier `{}`", name),
+            TokenType::Integer(num) => write!(f, "integer `{}`", num),
+            TokenType::String(_) => write!(f, "string literal"),
+            TokenType::Boolean(true) => write!(f, "`nocap`"),
+            TokenType::Boolean(false) => write!(f, "`cap`"),
+            TokenType::Keyword(k) => write!(
+                f,
+                "`{}`",
+                match k {
+                    KeywordType::If => "sus",
+                    KeywordType::Else => "sussy",
+                    KeywordType::While => "edge",
+                    KeywordType::Function => "skibidi",
+                    KeywordType::VarDef => "looksmaxxing",
+                    KeywordType::Break => "yeet",
+                    KeywordType::Continue => "bet",
+                    KeywordType::In => "in",
+                    KeywordType::Struct => "gang",
+                }
+            ),
+            TokenType::Operator(op) => write!(
+                f,
+                "`{}`",
+                match op {
+                    OperatorType::Addition => "rizz",
+                    OperatorType::Subtraction => "fanumtax",
+                    OperatorType::Multiplication => "gyatt",
+                    OperatorType::Division => "mog",
+                    OperatorType::Modulo => "mod",
+                    OperatorType::BitAnd => "bitand",
+                    OperatorType::BitOr => "bitor",
+                    OperatorType::BitXor => "bitxor",
+                    OperatorType::ShiftLeft => "shl",
+                    OperatorType::ShiftRight => "shr",
+                    OperatorType::Equals => "==",
+                    OperatorType::NotEquals => "!=",
+                    OperatorType::Less => "<",
+                    OperatorType::Greater => ">",
+                    OperatorType::LessEquals => "<=",
+                    OperatorType::GreaterEquals => ">=",
+                    OperatorType::And => "and",
+                    OperatorType::Or => "or",
+                    OperatorType::Not => "not",
+                    OperatorType::Negate => "-",
+                    OperatorType::BitNot => "bitnot",
+                }
+            ),
+            TokenType::LeftParan => write!(f, "`(`"),
+            TokenType::LeftSquare => write!(f, "`[`"),
+            TokenType::RightSquare => write!(f, "`]`"),
+            TokenType::Semicolon => write!(f, "`;`"),
+            TokenType::RightParan => write!(f, "`)`"),
+            TokenType::LeftBrack => write!(f, "`>>`"),
+            TokenType::RightBrack => write!(f, "`<<`"),
+            TokenType::Assignment => write!(f, "`=`"),
+            TokenType::Return => write!(f, "`sigma`"),
+            TokenType::Comma => write!(f, "`,`"),
+            TokenType::Colon => write!(f, "`:`"),
+            TokenType::Dot => write!(f, "`.`"),
+            TokenType::DotDot => write!(f, "`..`"),
+            TokenType::Pipe => write!(f, "`|`"),
+            TokenType::Comment(_) => write!(f, "comment"),
+            TokenType::Eof => write!(f, "end of file"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub index: usize,
+    pub span: Span,
 }
 
 pub struct Lexer {
     idx: usize,
     code: String,
+    file: Rc<str>,
+    scanned: usize,    // Newlines are counted up to here
+    line: usize,       // Line of the byte at scanned
+    line_start: usize, // Where that line starts
 }
 
 impl Lexer {
@@ -54,26 +221,172 @@ impl Lexer {
             "edge" => TokenType::Keyword(KeywordType::While),
             "sus" => TokenType::Keyword(KeywordType::If),
             "sussy" => TokenType::Keyword(KeywordType::Else),
+            "yeet" => TokenType::Keyword(KeywordType::Break),
+            "bet" => TokenType::Keyword(KeywordType::Continue),
+            "in" => TokenType::Keyword(KeywordType::In),
+            "gang" => TokenType::Keyword(KeywordType::Struct),
             "rizz" => TokenType::Operator(OperatorType::Addition),
             "fanumtax" => TokenType::Operator(OperatorType::Subtraction),
             "gyatt" => TokenType::Operator(OperatorType::Multiplication),
             "mog" => TokenType::Operator(OperatorType::Division),
+            "mod" => TokenType::Operator(OperatorType::Modulo),
+            "bitand" => TokenType::Operator(OperatorType::BitAnd),
+            "bitor" => TokenType::Operator(OperatorType::BitOr),
+            "bitxor" => TokenType::Operator(OperatorType::BitXor),
+            "bitnot" => TokenType::Operator(OperatorType::BitNot),
+            "shl" => TokenType::Operator(OperatorType::ShiftLeft),
+            "shr" => TokenType::Operator(OperatorType::ShiftRight),
+            "and" => TokenType::Operator(OperatorType::And),
+            "or" => TokenType::Operator(OperatorType::Or),
+            "not" => TokenType::Operator(OperatorType::Not),
             "sigma" => TokenType::Return,
+            "nocap" => TokenType::Boolean(true),
+            "cap" => TokenType::Boolean(false),
 
             _ => TokenType::Identifier(string),
         }
     }
     fn peek(&self) -> Option<char> {
-        self.code.chars().nth(self.idx + 1)
+        self.code[self.idx..].chars().nth(1)
     }
     fn current(&self) -> Option<char> {
-        self.code.chars().nth(self.idx)
+        self.code[self.idx..].chars().next()
+    }
+
+    // Skips to the end of the line, the newline itself isn't part of the comment
+    fn line_comment(&mut self) -> TokenType {
+        let start = self.idx;
+        self.idx = self.code[start..]
+            .find('\n')
+            .map_or(self.code.len(), |i| start + i);
+        TokenType::Comment(self.code[start..self.idx].trim_end().to_string())
+    }
+
+    // Block comments nest, so every /* needs its own */
+    fn block_comment(&mut self) -> Result<TokenType, Diagnostic> {
+        let start = self.idx;
+        let mut depth = 0;
+        loop {
+            match (self.current(), self.peek()) {
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    self.idx += 2;
+                }
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    self.idx += 2;
+                    if depth == 0 {
+                        return Ok(TokenType::Comment(self.code[start..self.idx].to_string()));
+                    }
+                }
+                (Some(c), _) => self.idx += c.len_utf8(),
+                (None, _) => {
+                    let mut span = self.span(start);
+                    span.end = start + 2;
+                    return Err(Diagnostic::error("E0002", "unterminated block comment")
+                        .with_label(&span, "comment starts here")
+                        .with_note("block comments nest, every `/*` needs a matching `*/`"));
+                }
+            }
+        }
     }
 
-    pub fn new(code: String) -> Self {
-        Lexer { code, idx: 0 }
+    // Reads an integer literal. It can be one larger than the largest int so that the smallest
+    // int can be written, the parser only accepts that when it is negated
+    fn number(&mut self) -> Result<TokenType, Diagnostic> {
+        let start = self.idx;
+        let mut num: Option<u64> = Some(0);
+        while let Some(digit) = self.current().and_then(|c| c.to_digit(10)) {
+            num = num
+                .and_then(|num| num.checked_mul(10))
+                .and_then(|num| num.checked_add(digit as u64));
+            self.idx += 1;
+        }
+
+        match num.filter(|&num| num <= i64::MIN.unsigned_abs()) {
+            Some(num) => Ok(TokenType::Integer(num)),
+            None => Err(literal_too_large(&self.span(start))),
+        }
     }
-    pub fn lex(&mut self) -> Result<Vec<Token>, String> {
+
+    // Reads a "..." literal, a raw newline isn't allowed so a missing quote can't eat the file
+    fn string(&mut self) -> Result<TokenType, Diagnostic> {
+        let start = self.idx;
+        let mut value = String::new();
+        self.idx += 1;
+        loop {
+            match self.current() {
+                Some('"') => {
+                    self.idx += 1;
+                    return Ok(TokenType::String(value));
+                }
+                Some('\\') => {
+                    let escape_start = self.idx;
+                    self.idx += 1;
+                    value.push(match self.current() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        c => {
+                            self.idx += c.map_or(0, |c| c.len_utf8());
+                            let mut span = self.span(escape_start);
+                            span.end = self.idx;
+                            return Err(Diagnostic::error("E0004", "unknown escape sequence")
+                                .with_label(&span, "not a valid escape")
+                                .with_note("valid escapes are \\n, \\t, \\r, \\\" and \\\\"));
+                        }
+                    });
+                    self.idx += 1;
+                }
+                Some(c) if c != '\n' => {
+                    value.push(c);
+                    self.idx += c.len_utf8();
+                }
+                _ => {
+                    let mut span = self.span(start);
+                    span.end = start + 1;
+                    return Err(Diagnostic::error("E0003", "unterminated string literal")
+                        .with_label(&span, "string starts here"));
+                }
+            }
+        }
+    }
+
+    // Line and column of the byte at start. Spans are asked for in source order, so the count
+    // carries on from the previous one instead of rescanning the file from the beginning
+    fn span(&mut self, start: usize) -> Span {
+        if start < self.scanned {
+            (self.scanned, self.line, self.line_start) = (0, 1, 0);
+        }
+        let skipped = &self.code[self.scanned..start];
+        if let Some(i) = skipped.rfind('\n') {
+            self.line += skipped.matches('\n').count();
+            self.line_start = self.scanned + i + 1;
+        }
+        self.scanned = start;
+
+        Span {
+            file: self.file.clone(),
+            start,
+            end: self.idx,
+            line: self.line,
+            col: self.code[self.line_start..start].chars().count() + 1,
+        }
+    }
+
+    pub fn new(code: String, file: &str) -> Self {
+        Lexer {
+            code,
+            idx: 0,
+            file: file.into(),
+            scanned: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+    pub fn lex(&mut self) -> Result<Vec<Token>, Diagnostic> {
         let mut tokens: Vec<Token> = Vec::new();
 
         while self.idx < self.code.len() {
@@ -83,21 +396,26 @@ impl Lexer {
                     self.idx += 1;
                     Some(TokenType::Pipe)
                 }
-                'a'..='z' => {
-                    while self.current().is_some_and(|c| c.is_ascii_alphabetic()) {
+                // A leading _ marks a name as deliberately unused, later ones separate words
+                '_' | 'a'..='z' => {
+                    while self.current() == Some('_') {
                         self.idx += 1;
                     }
-
-                    Some(self.check_identifier(self.code[start_idx..self.idx].to_string()))
-                }
-                '0'..='9' => {
-                    let mut num = 0;
-                    while self.current().is_some_and(|c| c.is_ascii_digit()) {
-                        num = num * 10 + self.current().unwrap().to_digit(10).unwrap() as i64;
+                    if !self.current().is_some_and(|c| c.is_ascii_alphabetic()) {
+                        return Err(Diagnostic::error("E0006", "expected a name after `_`")
+                            .with_label(&self.span(start_idx), "`_` on its own isn't a name")
+                            .with_note("names need a letter, like `_unused` or `my_var`"));
+                    }
+                    while self
+                        .current()
+                        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    {
                         self.idx += 1;
                     }
-                    Some(TokenType::Integer(num))
+
+                    Some(self.check_identifier(self.code[start_idx..self.idx].to_string()))
                 }
+                '0'..='9' => Some(self.number()?),
                 ' ' => {
                     self.idx += 1;
                     continue;
@@ -127,6 +445,11 @@ impl Lexer {
                         None
                     }
                 }
+                // Subtraction is fanumtax, so - is always negation
+                '-' => {
+                    self.idx += 1;
+                    Some(TokenType::Operator(OperatorType::Negate))
+                }
                 '(' => {
                     self.idx += 1;
                     Some(TokenType::LeftParan)
@@ -135,44 +458,104 @@ impl Lexer {
                     self.idx += 1;
                     Some(TokenType::RightParan)
                 }
-                '>' => {
-                    if self.peek() == Some('>') {
+                // A doubled > or < is always a block delimiter, so `a >> b` is never a comparison
+                '>' => match self.peek() {
+                    Some('>') => {
                         self.idx += 2;
                         Some(TokenType::LeftBrack)
-                    } else {
-                        None
                     }
-                }
-                '<' => {
-                    if self.peek() == Some('<') {
+                    Some('=') => {
+                        self.idx += 2;
+                        Some(TokenType::Operator(OperatorType::GreaterEquals))
+                    }
+                    _ => {
+                        self.idx += 1;
+                        Some(TokenType::Operator(OperatorType::Greater))
+                    }
+                },
+                '<' => match self.peek() {
+                    Some('<') => {
                         self.idx += 2;
                         Some(TokenType::RightBrack)
-                    } else {
-                        None
                     }
-                }
+                    Some('=') => {
+                        self.idx += 2;
+                        Some(TokenType::Operator(OperatorType::LessEquals))
+                    }
+                    _ => {
+                        self.idx += 1;
+                        Some(TokenType::Operator(OperatorType::Less))
+                    }
+                },
                 ',' => {
                     self.idx += 1;
                     Some(TokenType::Comma)
                 }
+                '[' => {
+                    self.idx += 1;
+                    Some(TokenType::LeftSquare)
+                }
+                ']' => {
+                    self.idx += 1;
+                    Some(TokenType::RightSquare)
+                }
+                ';' => {
+                    self.idx += 1;
+                    Some(TokenType::Semicolon)
+                }
+                ':' => {
+                    self.idx += 1;
+                    Some(TokenType::Colon)
+                }
+                '.' => {
+                    if self.peek() == Some('.') {
+                        self.idx += 2;
+                        Some(TokenType::DotDot)
+                    } else {
+                        self.idx += 1;
+                        Some(TokenType::Dot)
+                    }
+                }
+                '"' => Some(self.string()?),
+                '/' => match self.peek() {
+                    Some('/') => Some(self.line_comment()),
+                    Some('*') => Some(self.block_comment()?),
+                    _ => None,
+                },
                 _ => None,
             };
 
             if let Some(tok_typ) = tok_typ {
                 tokens.push(Token {
                     token_type: tok_typ,
-                    index: start_idx,
+                    span: self.span(start_idx),
                 });
                 continue;
             }
 
-            return Err(format!(
-                "Something wrong here with this token: {:?} at index: {}",
-                self.code.clone()[start_idx..].chars().next().unwrap(),
-                start_idx
-            ));
+            let c = self.code[start_idx..].chars().next().unwrap();
+            let mut span = self.span(start_idx);
+            span.end = start_idx + c.len_utf8();
+            return Err(
+                Diagnostic::error("E0001", format!("unexpected character {:?}", c))
+                    .with_label(&span, "not a valid token"),
+            );
         }
 
+        // The parser never has to check for running out of tokens, it stops at Eof instead
+        let end = self.code.trim_end().len();
+        self.idx = end;
+        tokens.push(Token {
+            token_type: TokenType::Eof,
+            span: self.span(end),
+        });
+
         Ok(tokens)
     }
 }
+
+pub fn literal_too_large(span: &Span) -> Diagnostic {
+    Diagnostic::error("E0005", "integer literal is too large")
+        .with_label(span, "doesn't fit in an int")
+        .with_note(format!("the largest int is {}", i64::MAX))
+}
@@ -0,0 +1,872 @@
+use crate::ir::*;
+use crate::lexer::{OperatorType, Span};
+use crate::parser::*;
+use crate::resolve::*;
+use crate::types::*;
+
+// Lowers the AST into the IR, one IrFunction per skibidi plus one for the top level.
+// Nested functions are lifted to the top level using the Resolution, the variables they capture
+// are passed to them as pointers.
+
+struct FunctionBuilder {
+    func: IrFunction,
+    id: FuncId,
+    current: Option<usize>, // Index of the block being filled, None after a return
+    insts: Vec<Inst>,
+    loops: Vec<(BlockId, BlockId)>, // Where continue and break jump to in the enclosing loops
+}
+
+impl FunctionBuilder {
+    // A slot for a value that only lowering needs, like the result of an and. Source names
+    // can't contain digits, so the name can't clash with a variable
+    fn temp_slot(&mut self, typ: IrType) -> Slot {
+        self.func
+            .slots
+            .push(format!("tmp{}", self.func.slots.len()));
+        self.func.slot_types.push(SlotType::Value(typ));
+        self.func.slots.len() - 1
+    }
+
+    fn reg(&mut self, typ: IrType) -> Reg {
+        self.func.regs.push(typ);
+        self.func.regs.len() - 1
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        // Placeholder terminator, every block is terminated before lowering finishes
+        self.func.blocks.push(Block {
+            id: self.func.blocks.len(),
+            insts: vec![],
+            terminator: Terminator::Jump(0),
+        });
+        self.func.blocks.len() - 1
+    }
+
+    // Ends the current block, code after a terminator goes into a fresh unreachable block
+    fn terminate(&mut self, terminator: Terminator) {
+        if let Some(current) = self.current {
+            let block = &mut self.func.blocks[current];
+            block.insts = std::mem::take(&mut self.insts);
+            block.terminator = terminator;
+        }
+        self.current = None;
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        if self.current.is_some() {
+            self.terminate(Terminator::Jump(block));
+        }
+        self.current = Some(block);
+    }
+
+    fn ensure_block(&mut self) {
+        if self.current.is_none() {
+            let block = self.new_block();
+            self.current = Some(block);
+        }
+    }
+}
+
+pub struct Lowerer {
+    program: Program,
+    resolution: Resolution,
+    types: Types,
+    functions: Vec<IrFunction>,
+    bounds_checks: bool, // Whether indexing stops the program when the index is out of bounds
+    overflow_checks: bool, // Whether arithmetic stops the program when it overflows
+}
+
+impl Lowerer {
+    pub fn new(program: Program, resolution: Resolution, types: Types) -> Self {
+        Self {
+            resolution,
+            types,
+            program,
+            functions: vec![],
+            bounds_checks: false,
+            overflow_checks: false,
+        }
+    }
+
+    pub fn with_bounds_checks(mut self, bounds_checks: bool) -> Self {
+        self.bounds_checks = bounds_checks;
+        self
+    }
+
+    pub fn with_overflow_checks(mut self, overflow_checks: bool) -> Self {
+        self.overflow_checks = overflow_checks;
+        self
+    }
+
+    pub fn lower(&mut self) -> IrProgram {
+        self.lower_function(MAIN, &self.program.statements.clone());
+        // Functions are pushed when they are done, so main ends up last
+        let main = self.functions.pop().unwrap();
+        self.functions.insert(0, main);
+
+        let structs = self
+            .program
+            .structs
+            .iter()
+            .map(|decl| IrStruct {
+                name: decl.name.clone(),
+                fields: decl
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.clone(), ir_type(field.typ)))
+                    .collect(),
+                span: decl.span.clone(),
+            })
+            .collect();
+
+        IrProgram {
+            structs,
+            functions: std::mem::take(&mut self.functions),
+        }
+    }
+
+    fn slot(&self, id: FuncId, binding: BindingId) -> Slot {
+        self.resolution
+            .info(id)
+            .locals
+            .iter()
+            .position(|&b| b == binding)
+            .unwrap()
+    }
+
+    fn lower_function(&mut self, id: FuncId, body: &[Stmt]) {
+        let info = self.resolution.info(id).clone();
+        let mut builder = FunctionBuilder {
+            func: IrFunction {
+                name: info.name.clone(),
+                params: (0..info.params.len()).collect(),
+                captures: (info.params.len()..info.params.len() + info.captures.len()).collect(),
+                slots: info
+                    .locals
+                    .iter()
+                    .map(|&b| self.resolution.binding(id, b).name)
+                    .collect(),
+                // Captured variables are pointers, for arrays to their first element
+                slot_types: info
+                    .locals
+                    .iter()
+                    .map(|&b| match self.types.binding(b) {
+                        _ if info.captures.contains(&b) => SlotType::Value(IrType::Ptr),
+                        Type::Array(len) => SlotType::Array(len),
+                        typ => SlotType::Value(ir_type(typ)),
+                    })
+                    .collect(),
+                regs: vec![],
+                ret: ir_type(self.types.returns(id)),
+                blocks: vec![],
+            },
+            id,
+            current: None,
+            insts: vec![],
+            loops: vec![],
+        };
+        builder.ensure_block();
+
+        self.lower_stmts(&mut builder, body);
+
+        // Falling off the end returns 0. Semantic analysis made sure that can't happen in
+        // functions that return something else, so a block that is still open never gets here
+        if builder.current.is_some() && always_returns(body) {
+            builder.terminate(Terminator::Unreachable);
+        } else if builder.current.is_some() {
+            let zero = builder.reg(IrType::Int);
+            builder.emit(Inst::Const {
+                dst: zero,
+                value: 0,
+            });
+            builder.terminate(Terminator::Return(zero));
+        }
+
+        self.functions.push(builder.func);
+    }
+
+    fn lower_stmts(&mut self, b: &mut FunctionBuilder, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let Stmt::Function(func) = stmt {
+                let id = self.resolution.function(func);
+                self.lower_function(id, &func.body);
+                continue;
+            }
+
+            b.ensure_block();
+            let span = stmt.span();
+            b.emit(Inst::Line {
+                file: span.file.to_string(),
+                line: span.line,
+            });
+            match stmt {
+                Stmt::Assignment(AssignmentStmt {
+                    expr: Expr::Array(array),
+                    span,
+                    ..
+                }) => {
+                    let var = self.resolution.variable(b.id, span);
+                    self.lower_array(b, &var, array);
+                }
+                Stmt::IndexAssignment(ass_stmt) => {
+                    let ptr = self.element(b, &ass_stmt.span, &ass_stmt.index);
+                    let value = self.lower_expr(b, &ass_stmt.expr);
+                    b.emit(Inst::StorePtr { ptr, src: value });
+                }
+                Stmt::FieldAssignment(ass_stmt) => {
+                    let ptr = self.field(b, &ass_stmt.target);
+                    let value = self.lower_expr(b, &ass_stmt.expr);
+                    b.emit(Inst::StorePtr { ptr, src: value });
+                }
+                Stmt::Assignment(ass_stmt) => {
+                    let value = self.lower_expr(b, &ass_stmt.expr);
+                    let var = self.resolution.variable(b.id, &ass_stmt.span);
+                    self.store(b, &var, value);
+                }
+                Stmt::If(if_stmt) => {
+                    // Every condition that fails falls through to the test of the next branch.
+                    // The end block is made last to keep the blocks in source order, so the
+                    // jumps to it are patched in afterwards
+                    let mut exits = vec![];
+                    for branch in &if_stmt.branches {
+                        let cond = self.lower_expr(b, &branch.condition);
+                        let then = b.new_block();
+                        let otherwise = b.new_block();
+                        b.terminate(Terminator::Branch {
+                            cond,
+                            then,
+                            otherwise,
+                        });
+
+                        b.current = Some(then);
+                        self.lower_stmts(b, &branch.body);
+                        if let Some(exit) = b.current {
+                            exits.push(exit);
+                            b.terminate(Terminator::Jump(0));
+                        }
+
+                        b.current = Some(otherwise);
+                    }
+
+                    self.lower_stmts(b, &if_stmt.else_body);
+                    let end = b.new_block();
+                    for exit in exits {
+                        b.func.blocks[exit].terminator = Terminator::Jump(end);
+                    }
+                    b.switch_to(end);
+                }
+                Stmt::Return(expr, _) => {
+                    let value = match expr.as_ref() {
+                        Some(expr) => self.lower_expr(b, expr),
+                        None => {
+                            let zero = b.reg(IrType::Int);
+                            b.emit(Inst::Const {
+                                dst: zero,
+                                value: 0,
+                            });
+                            zero
+                        }
+                    };
+                    b.terminate(Terminator::Return(value));
+                }
+                Stmt::While(while_stmt) => {
+                    let header = b.new_block();
+                    let body = b.new_block();
+                    let exit = b.new_block();
+
+                    b.switch_to(header);
+                    let cond = self.lower_expr(b, &while_stmt.condition);
+                    b.terminate(Terminator::Branch {
+                        cond,
+                        then: body,
+                        otherwise: exit,
+                    });
+
+                    b.current = Some(body);
+                    b.loops.push((header, exit));
+                    self.lower_stmts(b, &while_stmt.body);
+                    b.loops.pop();
+                    b.terminate(Terminator::Jump(header));
+
+                    b.current = Some(exit);
+                }
+                Stmt::For(for_stmt) => {
+                    let var = self.resolution.variable(b.id, &for_stmt.var_span);
+                    let end = self.resolution.variable(b.id, &for_stmt.span);
+                    let start = self.lower_expr(b, &for_stmt.start);
+                    self.store(b, &var, start);
+                    let value = self.lower_expr(b, &for_stmt.end);
+                    self.store(b, &end, value);
+
+                    let header = b.new_block();
+                    let body = b.new_block();
+                    let latch = b.new_block();
+                    let exit = b.new_block();
+
+                    b.switch_to(header);
+                    let counter = self.load(b, &var, IrType::Int);
+                    let limit = self.load(b, &end, IrType::Int);
+                    let cond = b.reg(IrType::Bool);
+                    b.emit(Inst::Binary {
+                        dst: cond,
+                        op: OperatorType::Less,
+                        left: counter,
+                        right: limit,
+                    });
+                    b.terminate(Terminator::Branch {
+                        cond,
+                        then: body,
+                        otherwise: exit,
+                    });
+
+                    // bet jumps to the increment, not straight back to the check
+                    b.current = Some(body);
+                    b.loops.push((latch, exit));
+                    self.lower_stmts(b, &for_stmt.body);
+                    b.loops.pop();
+                    b.switch_to(latch);
+
+                    let counter = self.load(b, &var, IrType::Int);
+                    let one = b.reg(IrType::Int);
+                    b.emit(Inst::Const { dst: one, value: 1 });
+                    let next = b.reg(IrType::Int);
+                    b.emit(Inst::Binary {
+                        dst: next,
+                        op: OperatorType::Addition,
+                        left: counter,
+                        right: one,
+                    });
+                    self.store(b, &var, next);
+                    b.terminate(Terminator::Jump(header));
+
+                    b.current = Some(exit);
+                }
+                Stmt::Break(_) => {
+                    let (_, exit) = *b.loops.last().unwrap();
+                    b.terminate(Terminator::Jump(exit));
+                }
+                Stmt::Continue(_) => {
+                    let (header, _) = *b.loops.last().unwrap();
+                    b.terminate(Terminator::Jump(header));
+                }
+                Stmt::Function(_) => unreachable!(),
+            }
+        }
+    }
+
+    fn store(&self, b: &mut FunctionBuilder, var: &Variable, value: Reg) {
+        match var.kind {
+            // Nothing can read _, so storing to it is a no-op
+            VarKind::Global => {}
+            VarKind::Local => b.emit(Inst::Store {
+                slot: self.slot(b.id, var.id),
+                src: value,
+            }),
+            VarKind::Captured => {
+                let ptr = b.reg(IrType::Ptr);
+                b.emit(Inst::Load {
+                    dst: ptr,
+                    slot: self.slot(b.id, var.id),
+                });
+                b.emit(Inst::StorePtr { ptr, src: value });
+            }
+        }
+    }
+
+    // The address of the variable, captured variables already hold it
+    fn address(&self, b: &mut FunctionBuilder, var: &Variable) -> Reg {
+        let slot = self.slot(b.id, var.id);
+        let ptr = b.reg(IrType::Ptr);
+        match var.kind {
+            VarKind::Captured => b.emit(Inst::Load { dst: ptr, slot }),
+            _ => b.emit(Inst::AddrOf { dst: ptr, slot }),
+        }
+        ptr
+    }
+
+    // The address of the field. A struct that isn't in a variable, like one a call returned, is
+    // stored in a slot of its own first
+    fn field(&mut self, b: &mut FunctionBuilder, field: &FieldExpr) -> Reg {
+        let Type::Struct(id) = self.types.of(&field.expr) else {
+            unreachable!()
+        };
+        let base = match &field.expr {
+            Expr::Variable(_, span) => {
+                let var = self.resolution.variable(b.id, span);
+                self.address(b, &var)
+            }
+            Expr::Field(inner) => self.field(b, inner),
+            expr => {
+                let value = self.lower_expr(b, expr);
+                let slot = b.temp_slot(IrType::Struct(id));
+                b.emit(Inst::Store { slot, src: value });
+                let base = b.reg(IrType::Ptr);
+                b.emit(Inst::AddrOf { dst: base, slot });
+                base
+            }
+        };
+        let dst = b.reg(IrType::Ptr);
+        b.emit(Inst::Field {
+            dst,
+            base,
+            id,
+            field: self.program.structs[id].field(&field.field).unwrap(),
+        });
+        dst
+    }
+
+    // The address of array[index] for the array at span
+    fn element(&mut self, b: &mut FunctionBuilder, span: &Span, index: &Expr) -> Reg {
+        let var = self.resolution.variable(b.id, span);
+        let base = self.address(b, &var);
+        let index_span = index.span();
+        let index = self.lower_expr(b, index);
+        if self.bounds_checks {
+            let Type::Array(len) = self.types.binding(var.id) else {
+                unreachable!()
+            };
+            self.check_bounds(b, index, len, index_span);
+        }
+        let dst = b.reg(IrType::Ptr);
+        b.emit(Inst::Index { dst, base, index });
+        dst
+    }
+
+    // Stops the program unless 0 <= index < len, the note has the index just like in C
+    fn check_bounds(&mut self, b: &mut FunctionBuilder, index: Reg, len: usize, span: &Span) {
+        let zero = b.reg(IrType::Int);
+        b.emit(Inst::Const {
+            dst: zero,
+            value: 0,
+        });
+        let limit = b.reg(IrType::Int);
+        b.emit(Inst::Const {
+            dst: limit,
+            value: len as i64,
+        });
+        let above = b.reg(IrType::Bool);
+        b.emit(Inst::Binary {
+            dst: above,
+            op: OperatorType::GreaterEquals,
+            left: index,
+            right: zero,
+        });
+        let below = b.reg(IrType::Bool);
+        b.emit(Inst::Binary {
+            dst: below,
+            op: OperatorType::Less,
+            left: index,
+            right: limit,
+        });
+        let cond = b.reg(IrType::Bool);
+        b.emit(Inst::Binary {
+            dst: cond,
+            op: OperatorType::BitAnd,
+            left: above,
+            right: below,
+        });
+        b.emit(Inst::Check {
+            cond,
+            message: format!(
+                "{}  = note: the length is {} but the index is ",
+                runtime_error("E0502", "index out of bounds", span),
+                len
+            ),
+            value: Some(index),
+        });
+    }
+
+    // Fills in a newly defined array, a repeated value is stored by a loop
+    fn lower_array(&mut self, b: &mut FunctionBuilder, var: &Variable, array: &ArrayExpr) {
+        let Some(len) = array.repeat else {
+            for (i, element) in array.elements.iter().enumerate() {
+                let value = self.lower_expr(b, element);
+                let base = self.address(b, var);
+                let index = b.reg(IrType::Int);
+                b.emit(Inst::Const {
+                    dst: index,
+                    value: i as i64,
+                });
+                let ptr = b.reg(IrType::Ptr);
+                b.emit(Inst::Index {
+                    dst: ptr,
+                    base,
+                    index,
+                });
+                b.emit(Inst::StorePtr { ptr, src: value });
+            }
+            return;
+        };
+
+        let value = self.lower_expr(b, &array.elements[0]);
+        let counter = b.temp_slot(IrType::Int);
+        let zero = b.reg(IrType::Int);
+        b.emit(Inst::Const {
+            dst: zero,
+            value: 0,
+        });
+        b.emit(Inst::Store {
+            slot: counter,
+            src: zero,
+        });
+
+        let header = b.new_block();
+        let body = b.new_block();
+        let exit = b.new_block();
+
+        b.switch_to(header);
+        let index = b.reg(IrType::Int);
+        b.emit(Inst::Load {
+            dst: index,
+            slot: counter,
+        });
+        let limit = b.reg(IrType::Int);
+        b.emit(Inst::Const {
+            dst: limit,
+            value: len as i64,
+        });
+        let cond = b.reg(IrType::Bool);
+        b.emit(Inst::Binary {
+            dst: cond,
+            op: OperatorType::Less,
+            left: index,
+            right: limit,
+        });
+        b.terminate(Terminator::Branch {
+            cond,
+            then: body,
+            otherwise: exit,
+        });
+
+        b.current = Some(body);
+        let base = self.address(b, var);
+        let ptr = b.reg(IrType::Ptr);
+        b.emit(Inst::Index {
+            dst: ptr,
+            base,
+            index,
+        });
+        b.emit(Inst::StorePtr { ptr, src: value });
+        let one = b.reg(IrType::Int);
+        b.emit(Inst::Const { dst: one, value: 1 });
+        let next = b.reg(IrType::Int);
+        b.emit(Inst::Binary {
+            dst: next,
+            op: OperatorType::Addition,
+            left: index,
+            right: one,
+        });
+        b.emit(Inst::Store {
+            slot: counter,
+            src: next,
+        });
+        b.terminate(Terminator::Jump(header));
+
+        b.current = Some(exit);
+    }
+
+    fn load(&self, b: &mut FunctionBuilder, var: &Variable, typ: IrType) -> Reg {
+        let slot = self.slot(b.id, var.id);
+        match var.kind {
+            VarKind::Captured => {
+                let ptr = b.reg(IrType::Ptr);
+                b.emit(Inst::Load { dst: ptr, slot });
+                let dst = b.reg(typ);
+                b.emit(Inst::LoadPtr { dst, ptr });
+                dst
+            }
+            _ => {
+                let dst = b.reg(typ);
+                b.emit(Inst::Load { dst, slot });
+                dst
+            }
+        }
+    }
+
+    fn lower_expr(&mut self, b: &mut FunctionBuilder, expr: &Expr) -> Reg {
+        match expr {
+            Expr::Binary(bin_expr)
+                if matches!(bin_expr.op, OperatorType::And | OperatorType::Or) =>
+            {
+                // The result goes through a slot of its own since the IR has no phis. The right
+                // side gets its own block that the left side can skip
+                let slot = b.temp_slot(IrType::Bool);
+                let left = self.lower_expr(b, &bin_expr.left);
+                b.emit(Inst::Store { slot, src: left });
+
+                let right_block = b.new_block();
+                let end = b.new_block();
+                let (then, otherwise) = match bin_expr.op {
+                    OperatorType::And => (right_block, end),
+                    _ => (end, right_block),
+                };
+                b.terminate(Terminator::Branch {
+                    cond: left,
+                    then,
+                    otherwise,
+                });
+
+                b.current = Some(right_block);
+                let right = self.lower_expr(b, &bin_expr.right);
+                b.emit(Inst::Store { slot, src: right });
+                b.switch_to(end);
+
+                let dst = b.reg(IrType::Bool);
+                b.emit(Inst::Load { dst, slot });
+                dst
+            }
+            Expr::Binary(bin_expr) => {
+                let left = self.lower_expr(b, &bin_expr.left);
+                let right = self.lower_expr(b, &bin_expr.right);
+                if matches!(bin_expr.op, OperatorType::Division | OperatorType::Modulo) {
+                    let zero = b.reg(IrType::Int);
+                    b.emit(Inst::Const {
+                        dst: zero,
+                        value: 0,
+                    });
+                    let cond = b.reg(IrType::Bool);
+                    b.emit(Inst::Binary {
+                        dst: cond,
+                        op: OperatorType::NotEquals,
+                        left: right,
+                        right: zero,
+                    });
+                    b.emit(Inst::Check {
+                        cond,
+                        message: runtime_error(
+                            "E0500",
+                            "attempt to divide by zero",
+                            &bin_expr.span,
+                        ),
+                        value: None,
+                    });
+                }
+                let typ = if bin_expr.op.is_comparison() {
+                    IrType::Bool
+                } else {
+                    IrType::Int
+                };
+                let dst = b.reg(typ);
+                match overflow(bin_expr.op) {
+                    Some(what) if self.overflow_checks => b.emit(Inst::CheckedBinary {
+                        dst,
+                        op: bin_expr.op,
+                        left,
+                        right,
+                        message: runtime_error(
+                            "E0503",
+                            &format!("attempt to {} with overflow", what),
+                            &bin_expr.span,
+                        ),
+                    }),
+                    _ => b.emit(Inst::Binary {
+                        dst,
+                        op: bin_expr.op,
+                        left,
+                        right,
+                    }),
+                }
+                dst
+            }
+            Expr::Unary(unary_expr) => {
+                let value = self.lower_expr(b, &unary_expr.expr);
+                match unary_expr.op {
+                    // not x is x == 0
+                    OperatorType::Not => {
+                        let zero = b.reg(IrType::Bool);
+                        b.emit(Inst::Const {
+                            dst: zero,
+                            value: 0,
+                        });
+                        let dst = b.reg(IrType::Bool);
+                        b.emit(Inst::Binary {
+                            dst,
+                            op: OperatorType::Equals,
+                            left: value,
+                            right: zero,
+                        });
+                        dst
+                    }
+                    // bitnot x is x xor -1
+                    OperatorType::BitNot => {
+                        let ones = b.reg(IrType::Int);
+                        b.emit(Inst::Const {
+                            dst: ones,
+                            value: -1,
+                        });
+                        let dst = b.reg(IrType::Int);
+                        b.emit(Inst::Binary {
+                            dst,
+                            op: OperatorType::BitXor,
+                            left: value,
+                            right: ones,
+                        });
+                        dst
+                    }
+                    // -x is 0 - x
+                    OperatorType::Negate => {
+                        let zero = b.reg(IrType::Int);
+                        b.emit(Inst::Const {
+                            dst: zero,
+                            value: 0,
+                        });
+                        let dst = b.reg(IrType::Int);
+                        if self.overflow_checks {
+                            b.emit(Inst::CheckedBinary {
+                                dst,
+                                op: OperatorType::Subtraction,
+                                left: zero,
+                                right: value,
+                                message: runtime_error(
+                                    "E0503",
+                                    "attempt to negate with overflow",
+                                    &unary_expr.span,
+                                ),
+                            });
+                        } else {
+                            b.emit(Inst::Binary {
+                                dst,
+                                op: OperatorType::Subtraction,
+                                left: zero,
+                                right: value,
+                            });
+                        }
+                        dst
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Index(index) => {
+                let ptr = self.element(b, &index.span, &index.index);
+                let dst = b.reg(IrType::Int);
+                b.emit(Inst::LoadPtr { dst, ptr });
+                dst
+            }
+            // Arrays only show up in definitions, which lower_stmts handles
+            Expr::Array(_) => unreachable!(),
+            Expr::Call(name, params, span)
+                if name == "len" && self.resolution.callee(span).is_none() =>
+            {
+                let Type::Array(len) = self.types.of(&params[0]) else {
+                    unreachable!()
+                };
+                let dst = b.reg(IrType::Int);
+                b.emit(Inst::Const {
+                    dst,
+                    value: len as i64,
+                });
+                dst
+            }
+            Expr::Call(_, params, span) => {
+                let mut args: Vec<Reg> = params.iter().map(|p| self.lower_expr(b, p)).collect();
+
+                let callee = match self.resolution.callee(span) {
+                    Some(id) => {
+                        let info = self.resolution.info(id).clone();
+                        // Captured variables are passed as pointers after the arguments
+                        for binding in info.captures {
+                            let var = self.resolution.binding(b.id, binding);
+                            args.push(self.address(b, &var));
+                        }
+                        Callee::Function(info.name)
+                    }
+                    // print is overloaded for strings
+                    None if args.first().is_some_and(|&a| b.func.regs[a] == IrType::Str) => {
+                        Callee::PrintStr
+                    }
+                    None => Callee::Print,
+                };
+
+                let dst = b.reg(ir_type(self.types.of(expr)));
+                b.emit(Inst::Call { dst, callee, args });
+                dst
+            }
+            Expr::Number(num, _) => {
+                let dst = b.reg(IrType::Int);
+                b.emit(Inst::Const { dst, value: *num });
+                dst
+            }
+            Expr::Bool(value, _) => {
+                let dst = b.reg(IrType::Bool);
+                b.emit(Inst::Const {
+                    dst,
+                    value: *value as i64,
+                });
+                dst
+            }
+            Expr::Str(value, _) => {
+                let dst = b.reg(IrType::Str);
+                b.emit(Inst::Str {
+                    dst,
+                    value: value.clone(),
+                });
+                dst
+            }
+            Expr::Variable(_, span) => {
+                let var = self.resolution.variable(b.id, span);
+                self.load(b, &var, ir_type(self.types.of(expr)))
+            }
+            // The fields are stored into a slot in the order they are written
+            Expr::Struct(struct_expr) => {
+                let id = struct_expr.id;
+                let slot = b.temp_slot(IrType::Struct(id));
+                let base = b.reg(IrType::Ptr);
+                b.emit(Inst::AddrOf { dst: base, slot });
+                for init in &struct_expr.fields {
+                    let value = self.lower_expr(b, &init.expr);
+                    let ptr = b.reg(IrType::Ptr);
+                    b.emit(Inst::Field {
+                        dst: ptr,
+                        base,
+                        id,
+                        field: self.program.structs[id].field(&init.name).unwrap(),
+                    });
+                    b.emit(Inst::StorePtr { ptr, src: value });
+                }
+                let dst = b.reg(IrType::Struct(id));
+                b.emit(Inst::Load { dst, slot });
+                dst
+            }
+            Expr::Field(field) => {
+                let ptr = self.field(b, field);
+                let dst = b.reg(ir_type(self.types.of(expr)));
+                b.emit(Inst::LoadPtr { dst, ptr });
+                dst
+            }
+        }
+    }
+}
+
+// What the overflow check for op says it attempted, None for the operators that can't overflow
+fn overflow(op: OperatorType) -> Option<&'static str> {
+    match op {
+        OperatorType::Addition => Some("add"),
+        OperatorType::Subtraction => Some("subtract"),
+        OperatorType::Multiplication => Some("multiply"),
+        OperatorType::Division => Some("divide"),
+        OperatorType::Modulo => Some("calculate the remainder"),
+        _ => None,
+    }
+}
+
+// The message a failed check prints, like a compile error but without the source line
+fn runtime_error(code: &str, message: &str, span: &Span) -> String {
+    format!(
+        "error[{}]: {}\n --> {}:{}:{}\n",
+        code, message, span.file, span.line, span.col
+    )
+}
+
+fn ir_type(typ: Type) -> IrType {
+    match typ {
+        Type::Int => IrType::Int,
+        Type::Bool => IrType::Bool,
+        Type::Str => IrType::Str,
+        Type::Array(_) => IrType::Ptr,
+        Type::Struct(id) => IrType::Struct(id),
+    }
+}
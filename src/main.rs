@@ -1,167 +1,477 @@
-mod codegen;
-mod lexer;
-mod parser;
-mod semantic;
-
-use codegen::*;
-use lexer::*;
-use parser::*;
-use semantic::*;
-
-use clap::{Parser as ClapParser, Subcommand};
-use std::fs::{remove_file, File};
-use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
-
-#[derive(ClapParser)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
-}
-
-#[derive(Subcommand)]
-enum Commands {
-    /// Transpile the specified file to C code
-    Transpile {
-        #[arg(short, long)]
-        verbose: bool,
-        file: PathBuf,
-    },
-    /// Compile the specified file
-    Compile {
-        #[arg(short, long)]
-        verbose: bool,
-        file: PathBuf,
-        out_file: PathBuf,
-    },
-    /// Run the specified file
-    Run {
-        #[arg(short, long)]
-        verbose: bool,
-        file: PathBuf,
-    },
-    /// Generate the AST for the specified file
-    Ast {
-        #[arg(short, long)]
-        verbose: bool,
-        file: PathBuf,
-    },
-}
-
-fn read_file(file: &PathBuf) -> String {
-    let code = std::fs::read_to_string(file);
-
-    if code.is_err() {
-        panic!("Error reading file: {:?}", code.err().unwrap());
-    }
-
-    code.unwrap()
-}
-
-fn lex(code: String, v: bool) -> Vec<Token> {
-    let tokens = Lexer::new(code).lex();
-
-    if tokens.is_err() {
-        panic!("Lexing error: {:?}", tokens.err().unwrap());
-    }
-
-    if v {
-        println!("Tokens:\n{:?}", tokens.clone().unwrap());
-    }
-
-    tokens.unwrap()
-}
-
-fn parse(tokens: Vec<Token>, v: bool) -> Program {
-    let prog = Parser::new(tokens).parse();
-
-    if prog.is_err() {
-        panic!("Parsing error: {:?}", prog.err().unwrap());
-    }
-
-    if v {
-        println!("AST:\n{:#?}", prog.clone().unwrap());
-    }
-
-    prog.unwrap()
-}
-
-fn analyze(prog: Program) {
-    let sem = Semantic::new(prog).analyze();
-
-    if sem.is_err() {
-        panic!("Semantic analysis error: {:?}", sem.err().unwrap());
-    }
-}
-
-fn transpile(prog: Program, v: bool) -> String {
-    let code = CodeGenerator::new(prog).generate();
-
-    if code.is_err() {
-        panic!("Code generation error: {:?}", code.err().unwrap());
-    }
-
-    if v {
-        println!("C code:\n{}", code.clone().unwrap());
-    }
-
-    code.unwrap()
-}
-
-fn compile(c_code: String, out_file: PathBuf) {
-    let mut file = File::create(".skibidi.c").unwrap();
-    file.write_all(c_code.as_bytes()).unwrap();
-
-    Command::new("gcc")
-        .arg(".skibidi.c")
-        .arg("-o")
-        .arg(out_file)
-        .output()
-        .expect("Failed to compile with gcc, make sure gcc is installed");
-
-    remove_file(".skibidi.c").unwrap();
-}
-
-fn main() {
-    let cli = Cli::parse();
-
-    match &cli.command {
-        Commands::Transpile { verbose, file } => {
-            let code = read_file(file);
-            let tokens = lex(code, *verbose);
-            let prog = parse(tokens, *verbose);
-            analyze(prog.clone());
-            transpile(prog, true);
-        }
-        Commands::Compile {
-            verbose,
-            file,
-            out_file,
-        } => {
-            let code = read_file(file);
-            let tokens = lex(code, *verbose);
-            let prog = parse(tokens, *verbose);
-            analyze(prog.clone());
-            let c_code = transpile(prog, *verbose);
-            compile(c_code, out_file.clone());
-        }
-        Commands::Run { verbose, file } => {
-            let code = read_file(file);
-            let tokens = lex(code, *verbose);
-            let prog = parse(tokens, *verbose);
-            analyze(prog.clone());
-            let c_code = transpile(prog, *verbose);
-            compile(c_code, ".skibidi.temp".into());
-            let output = Command::new("./.skibidi.temp").output().unwrap();
-            println!("{}", String::from_utf8_lossy(&output.stdout));
-            remove_file(".skibidi.temp").unwrap();
-        }
-        Commands::Ast { verbose, file } => {
-            let code = read_file(file);
-            let tokens = lex(code, *verbose);
-            parse(tokens, true);
-        }
-    }
-}
+mod asm;
+mod codegen;
+mod diagnostics;
+mod interpreter;
+mod ir;
+mod lexer;
+mod lower;
+mod optimize;
+mod parser;
+mod resolve;
+mod semantic;
+mod types;
+
+use asm::*;
+use codegen::*;
+use diagnostics::*;
+use interpreter::*;
+use ir::*;
+use lexer::*;
+use lower::*;
+use optimize::*;
+use parser::*;
+use resolve::Resolution;
+use semantic::*;
+use types::*;
+
+use clap::{Args, Parser as ClapParser, Subcommand, ValueEnum};
+use std::fs::{remove_file, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+#[derive(ClapParser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    /// Stop with an error if there are any warnings
+    #[arg(long, global = true)]
+    deny_warnings: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Transpile to C and compile with gcc
+    C,
+    /// Emit x86-64 assembly and build with as and ld
+    Asm,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    /// C source code
+    C,
+    /// x86-64 assembly
+    Asm,
+    /// The intermediate representation both compiled backends are built on
+    Ir,
+}
+
+// Runtime checks that the compiled backends can add to the generated code, the interpreter always
+// checks bounds but only checks for overflow when asked to
+#[derive(Args, Clone, Copy)]
+struct Checks {
+    /// Stop with an error when an array index is out of bounds
+    #[arg(long)]
+    bounds_checks: bool,
+    /// Stop with an error when arithmetic overflows instead of wrapping around
+    #[arg(long)]
+    overflow_checks: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Transpile the specified file to C code
+    Transpile {
+        #[arg(short, long)]
+        verbose: bool,
+        /// What to print instead of C code
+        #[arg(short, long, value_enum, default_value = "c")]
+        emit: Emit,
+        #[command(flatten)]
+        checks: Checks,
+        file: PathBuf,
+    },
+    /// Compile the specified file
+    Compile {
+        #[arg(short, long)]
+        verbose: bool,
+        #[arg(short, long, value_enum, default_value = "c")]
+        backend: Backend,
+        #[command(flatten)]
+        checks: Checks,
+        file: PathBuf,
+        out_file: PathBuf,
+    },
+    /// Run the specified file
+    Run {
+        #[arg(short, long)]
+        verbose: bool,
+        #[arg(short, long, value_enum, default_value = "c")]
+        backend: Backend,
+        /// Evaluate the program directly instead of compiling it
+        #[arg(short, long, conflicts_with = "backend")]
+        interpret: bool,
+        #[command(flatten)]
+        checks: Checks,
+        file: PathBuf,
+    },
+    /// Generate the AST for the specified file
+    Ast {
+        #[arg(short, long)]
+        verbose: bool,
+        file: PathBuf,
+    },
+}
+
+// Prints the diagnostics and exits, a failed compile shouldn't look like a crash
+fn fail(diagnostics: &[Diagnostic], code: &str) -> ! {
+    emit(diagnostics, code);
+    std::process::exit(1);
+}
+
+fn read_file(file: &Path) -> String {
+    match std::fs::read_to_string(file) {
+        Ok(code) => code,
+        Err(err) => fail(
+            &[Diagnostic::error(
+                "E0400",
+                format!("couldn't read {}: {}", file.display(), err),
+            )],
+            "",
+        ),
+    }
+}
+
+fn lex(code: &str, file: &Path, v: bool) -> Vec<Token> {
+    let tokens = match Lexer::new(code.to_string(), &file.display().to_string()).lex() {
+        Ok(tokens) => tokens,
+        Err(err) => fail(&[err], code),
+    };
+
+    if v {
+        println!("Tokens:\n{:?}", tokens);
+    }
+
+    tokens
+}
+
+fn parse(tokens: Vec<Token>, code: &str, v: bool) -> Program {
+    let prog = match Parser::new(tokens).parse() {
+        Ok(prog) => prog,
+        Err(errs) => fail(&errs, code),
+    };
+
+    if v {
+        println!("AST:\n{:#?}", prog);
+    }
+
+    prog
+}
+
+// A program that passed analysis, along with what the backends need to know about it
+struct Analyzed {
+    prog: Program,
+    resolution: Resolution,
+    types: Types,
+}
+
+fn analyze(prog: Program, code: &str, deny_warnings: bool) -> Analyzed {
+    let resolution = match Semantic::new(prog.clone()).analyze() {
+        Ok((_, warnings)) if deny_warnings && !warnings.is_empty() => {
+            emit(&warnings, code);
+            fail(
+                &[Diagnostic::error(
+                    "E0403",
+                    match warnings.len() {
+                        1 => "stopping because of a warning".to_string(),
+                        n => format!("stopping because of {} warnings", n),
+                    },
+                )
+                .with_note("`--deny-warnings` turns warnings into errors")],
+                code,
+            );
+        }
+        Ok((resolution, warnings)) => {
+            emit(&warnings, code);
+            resolution
+        }
+        Err(err) => fail(&[err], code),
+    };
+    let types = match TypeChecker::new(&prog, &resolution).check() {
+        Ok(types) => types,
+        Err(err) => fail(&[err], code),
+    };
+
+    Analyzed {
+        prog,
+        resolution,
+        types,
+    }
+}
+
+fn transpile(analyzed: Analyzed, checks: Checks, v: bool) -> String {
+    let ir = lower(analyzed, checks, v);
+    let c_code = CodeGenerator::new(ir).generate();
+
+    if v {
+        println!("C code:\n{}", c_code);
+    }
+
+    c_code
+}
+
+fn lower(analyzed: Analyzed, checks: Checks, v: bool) -> IrProgram {
+    let mut ir = Lowerer::new(analyzed.prog, analyzed.resolution, analyzed.types)
+        .with_bounds_checks(checks.bounds_checks)
+        .with_overflow_checks(checks.overflow_checks)
+        .lower();
+    optimize(&mut ir);
+
+    if v {
+        println!("IR:\n{}", ir);
+    }
+
+    ir
+}
+
+fn generate_asm(analyzed: Analyzed, code: &str, checks: Checks, v: bool) -> String {
+    let ir = lower(analyzed, checks, v);
+    let asm = match AsmGenerator::new(ir).generate() {
+        Ok(asm) => asm,
+        Err(err) => fail(&[err], code),
+    };
+
+    if v {
+        println!("Assembly:\n{}", asm);
+    }
+
+    asm
+}
+
+fn interpret_program(analyzed: Analyzed, code: &str, checks: Checks) {
+    let mut stdout = std::io::stdout();
+    let result = Interpreter::new(
+        analyzed.prog,
+        analyzed.resolution,
+        analyzed.types,
+        &mut stdout,
+    )
+    .with_overflow_checks(checks.overflow_checks)
+    .run();
+    if let Err(err) = result {
+        let _ = stdout.flush();
+        fail(&[err], code);
+    }
+}
+
+// Runs an external tool, the error includes whatever the tool printed
+fn run_tool(command: &mut Command, tool: &str) -> Result<(), Diagnostic> {
+    match command.output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(Diagnostic::error(
+            "E0402",
+            format!("{} failed on the generated code", tool),
+        )
+        .with_note(String::from_utf8_lossy(&output.stderr).trim_end())),
+        Err(err) => Err(
+            Diagnostic::error("E0401", format!("failed to run {}: {}", tool, err))
+                .with_note(format!("make sure {} is installed", tool)),
+        ),
+    }
+}
+
+fn compile(c_code: String, out_file: PathBuf) {
+    let mut file = File::create(".skibidi.c").unwrap();
+    file.write_all(c_code.as_bytes()).unwrap();
+
+    let result = run_tool(
+        // Overflow wraps around like in the other backends instead of being undefined
+        Command::new("gcc")
+            .arg("-fwrapv")
+            .arg(".skibidi.c")
+            .arg("-o")
+            .arg(out_file),
+        "gcc",
+    );
+
+    remove_file(".skibidi.c").unwrap();
+
+    if let Err(err) = result {
+        fail(&[err], "");
+    }
+}
+
+fn assemble(asm: String, out_file: PathBuf) {
+    let mut file = File::create(".skibidi.s").unwrap();
+    file.write_all(asm.as_bytes()).unwrap();
+
+    let result = run_tool(
+        Command::new("as")
+            .arg(".skibidi.s")
+            .arg("-o")
+            .arg(".skibidi.o"),
+        "as",
+    )
+    .and_then(|_| {
+        run_tool(
+            Command::new("ld").arg(".skibidi.o").arg("-o").arg(out_file),
+            "ld",
+        )
+    });
+
+    remove_file(".skibidi.s").unwrap();
+    let _ = remove_file(".skibidi.o");
+
+    if let Err(err) = result {
+        fail(&[err], "");
+    }
+}
+
+fn build(
+    analyzed: Analyzed,
+    code: &str,
+    backend: Backend,
+    checks: Checks,
+    out_file: PathBuf,
+    v: bool,
+) {
+    match backend {
+        Backend::C => {
+            let c_code = transpile(analyzed, checks, v);
+            compile(c_code, out_file);
+        }
+        Backend::Asm => {
+            let asm = generate_asm(analyzed, code, checks, v);
+            assemble(asm, out_file);
+        }
+    }
+}
+
+// The signal that killed the program, a program that crashes has no exit code
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_: &ExitStatus) -> Option<i32> {
+    None
+}
+
+// Exits the way a shell would for a program killed by a signal, with 128 plus the signal
+fn report_signal(signal: i32) -> ! {
+    let name = match signal {
+        4 => " (SIGILL)",
+        6 => " (SIGABRT)",
+        7 => " (SIGBUS)",
+        8 => " (SIGFPE)",
+        11 => " (SIGSEGV)",
+        _ => "",
+    };
+    let mut err = Diagnostic::error(
+        "E0404",
+        format!("the program was killed by signal {}{}", signal, name),
+    );
+    if signal == 11 {
+        err = err.with_note("this usually means that recursion went too deep for the stack");
+    }
+    emit(&[err], "");
+    std::process::exit(128 + signal);
+}
+
+// The interpreter recurses for every skibidi call, this is enough for as deep as it lets calls
+// nest, even in a debug build
+const STACK_SIZE: usize = 1 << 30;
+
+fn main() {
+    let cli = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_cli)
+        .unwrap();
+
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn run_cli() {
+    let cli = Cli::parse();
+
+    match &cli.command {
+        Commands::Transpile {
+            verbose,
+            emit,
+            checks,
+            file,
+        } => {
+            let code = read_file(file);
+            let tokens = lex(&code, file, *verbose);
+            let prog = parse(tokens, &code, *verbose);
+            let analyzed = analyze(prog, &code, cli.deny_warnings);
+            match emit {
+                Emit::C => {
+                    transpile(analyzed, *checks, true);
+                }
+                Emit::Asm => {
+                    generate_asm(analyzed, &code, *checks, true);
+                }
+                Emit::Ir => {
+                    lower(analyzed, *checks, true);
+                }
+            }
+        }
+        Commands::Compile {
+            verbose,
+            backend,
+            checks,
+            file,
+            out_file,
+        } => {
+            let code = read_file(file);
+            let tokens = lex(&code, file, *verbose);
+            let prog = parse(tokens, &code, *verbose);
+            let analyzed = analyze(prog, &code, cli.deny_warnings);
+            build(
+                analyzed,
+                &code,
+                *backend,
+                *checks,
+                out_file.clone(),
+                *verbose,
+            );
+        }
+        Commands::Run {
+            verbose,
+            backend,
+            interpret,
+            checks,
+            file,
+        } => {
+            let code = read_file(file);
+            let tokens = lex(&code, file, *verbose);
+            let prog = parse(tokens, &code, *verbose);
+            let analyzed = analyze(prog, &code, cli.deny_warnings);
+            if *interpret {
+                interpret_program(analyzed, &code, *checks);
+                return;
+            }
+            build(
+                analyzed,
+                &code,
+                *backend,
+                *checks,
+                ".skibidi.temp".into(),
+                *verbose,
+            );
+            let output = Command::new("./.skibidi.temp").output().unwrap();
+            println!("{}", String::from_utf8_lossy(&output.stdout));
+            // Runtime checks in the generated code report to stderr
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            remove_file(".skibidi.temp").unwrap();
+            if let Some(signal) = signal(&output.status) {
+                report_signal(signal);
+            }
+            if !output.status.success() {
+                std::process::exit(output.status.code().unwrap_or(1));
+            }
+        }
+        Commands::Ast { verbose, file } => {
+            let code = read_file(file);
+            let tokens = lex(&code, file, *verbose);
+            parse(tokens, &code, true);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::*;
+use crate::OperatorType;
+
+// Passes that rewrite the IR before it reaches a backend
+
+pub fn optimize(program: &mut IrProgram) {
+    for func in &mut program.functions {
+        fold_constants(func);
+        remove_unreachable_blocks(func);
+        remove_dead_values(func);
+    }
+}
+
+// Computes operations whose operands are constants at compile time. A folded comparison can
+// decide a branch, so this repeats until nothing changes
+fn fold_constants(func: &mut IrFunction) {
+    // Registers are assigned once, so a constant register holds its value everywhere
+    let mut consts: HashMap<Reg, i64> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for block in &mut func.blocks {
+            for inst in &mut block.insts {
+                let folded = match inst {
+                    Inst::Const { dst, value } => {
+                        if consts.insert(*dst, *value).is_none() {
+                            changed = true;
+                        }
+                        continue;
+                    }
+                    Inst::Binary {
+                        dst,
+                        op,
+                        left,
+                        right,
+                    } => match (consts.get(left), consts.get(right)) {
+                        (Some(&a), Some(&b)) => evaluate(*op, a, b).map(|value| (*dst, value)),
+                        _ => None,
+                    },
+                    // A check that fails at compile time is left for the program to hit
+                    Inst::CheckedBinary {
+                        dst,
+                        op,
+                        left,
+                        right,
+                        ..
+                    } => match (consts.get(left), consts.get(right)) {
+                        (Some(&a), Some(&b)) => evaluate_checked(*op, a, b).map(|v| (*dst, v)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some((dst, value)) = folded {
+                    *inst = Inst::Const { dst, value };
+                    changed = true;
+                }
+            }
+
+            let before = block.insts.len();
+            block.insts.retain(|inst| match inst {
+                Inst::Check { cond, .. } => consts.get(cond).is_none_or(|&c| c == 0),
+                _ => true,
+            });
+            changed |= block.insts.len() != before;
+
+            if let Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } = block.terminator
+            {
+                if let Some(&c) = consts.get(&cond) {
+                    block.terminator = Terminator::Jump(if c != 0 { then } else { otherwise });
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+// The same wrapping arithmetic as the backends. Dividing by zero isn't folded, the check in
+// front of it stops the program first
+fn evaluate(op: OperatorType, a: i64, b: i64) -> Option<i64> {
+    let value = match op {
+        OperatorType::Addition => a.wrapping_add(b),
+        OperatorType::Subtraction => a.wrapping_sub(b),
+        OperatorType::Multiplication => a.wrapping_mul(b),
+        OperatorType::Division if b == 0 => return None,
+        OperatorType::Division => a.wrapping_div(b),
+        OperatorType::Modulo if b == 0 => return None,
+        OperatorType::Modulo => a.wrapping_rem(b),
+        OperatorType::BitAnd => a & b,
+        OperatorType::BitOr => a | b,
+        OperatorType::BitXor => a ^ b,
+        OperatorType::ShiftLeft => a.wrapping_shl((b & 63) as u32),
+        OperatorType::ShiftRight => a.wrapping_shr((b & 63) as u32),
+        OperatorType::Equals => (a == b) as i64,
+        OperatorType::NotEquals => (a != b) as i64,
+        OperatorType::Less => (a < b) as i64,
+        OperatorType::Greater => (a > b) as i64,
+        OperatorType::LessEquals => (a <= b) as i64,
+        OperatorType::GreaterEquals => (a >= b) as i64,
+        // Never in the IR, see Inst::Binary
+        OperatorType::And
+        | OperatorType::Or
+        | OperatorType::Not
+        | OperatorType::Negate
+        | OperatorType::BitNot => unreachable!(),
+    };
+    Some(value)
+}
+
+fn evaluate_checked(op: OperatorType, a: i64, b: i64) -> Option<i64> {
+    match op {
+        OperatorType::Addition => a.checked_add(b),
+        OperatorType::Subtraction => a.checked_sub(b),
+        OperatorType::Multiplication => a.checked_mul(b),
+        OperatorType::Division => a.checked_div(b),
+        OperatorType::Modulo => a.checked_rem(b),
+        _ => unreachable!(),
+    }
+}
+
+// Lowering starts a new block after every return, this drops the ones nothing jumps to
+fn remove_unreachable_blocks(func: &mut IrFunction) {
+    let mut reachable = HashSet::new();
+    let mut stack = vec![func.blocks[0].id];
+    while let Some(id) = stack.pop() {
+        if reachable.insert(id) {
+            let block = func.blocks.iter().find(|b| b.id == id).unwrap();
+            stack.extend(block.terminator.successors());
+        }
+    }
+
+    func.blocks.retain(|block| reachable.contains(&block.id));
+}
+
+// Drops instructions whose value nothing reads, like the operands of a folded constant.
+// Removing one can leave its own operands unused, so this repeats until nothing changes
+fn remove_dead_values(func: &mut IrFunction) {
+    loop {
+        let used: HashSet<Reg> = func
+            .blocks
+            .iter()
+            .flat_map(|block| {
+                block
+                    .insts
+                    .iter()
+                    .flat_map(Inst::uses)
+                    .chain(block.terminator.uses())
+            })
+            .collect();
+
+        let mut changed = false;
+        for block in &mut func.blocks {
+            let before = block.insts.len();
+            block
+                .insts
+                .retain(|inst| !inst.is_pure() || inst.dst().is_none_or(|dst| used.contains(&dst)));
+            changed |= block.insts.len() != before;
+        }
+        if !changed {
+            break;
+        }
+    }
+}
@@ -1,400 +1,996 @@
-use std::mem;
-use std::vec;
-
-use crate::lexer::*;
-
-#[derive(Debug, Clone)]
-pub enum Expr {
-    Binary(Box<BinaryExpr>),
-    Number(i64),
-    Variable(String),
-    Call(String, Vec<Expr>),
-}
-
-#[derive(Debug, Clone)]
-pub struct BinaryExpr {
-    pub left: Expr,
-    pub right: Expr,
-    pub op: OperatorType,
-}
-
-#[derive(Debug, Clone)]
-pub enum Stmt {
-    Assignment(AssignmentStmt),
-    If(IfStmt),
-    While(WhileStmt),
-    Function(FunctionStmt),
-    Return(Box<Option<Expr>>),
-}
-
-#[derive(Debug, Clone)]
-pub struct AssignmentStmt {
-    pub definition: bool,
-    pub name: String,
-    pub expr: Expr,
-}
-
-#[derive(Debug, Clone)]
-pub struct IfStmt {
-    pub condition: Expr,
-    pub body: Vec<Stmt>,
-    pub else_body: Vec<Stmt>,
-}
-
-#[derive(Debug, Clone)]
-pub struct WhileStmt {
-    pub condition: Expr,
-    pub body: Vec<Stmt>,
-}
-
-#[derive(Debug, Clone)]
-pub struct FunctionStmt {
-    pub name: String,
-    pub params: Vec<String>,
-    pub body: Vec<Stmt>,
-}
-
-#[derive(Debug, Clone)]
-pub struct Program {
-    pub statements: Vec<Stmt>,
-}
-
-pub struct Parser {
-    pub program: Program,
-    tokens: Vec<Token>,
-    idx: usize,
-}
-
-// This trait will extract the value from enums that have a value associated with them
-// For example, TokenType::Identifier("skibidi".to_string()) will be extracted to Some("skibidi")
-pub trait EnumExtractor<T> {
-    fn extract(&self) -> Option<&T>;
-}
-
-impl EnumExtractor<String> for TokenType {
-    fn extract(&self) -> Option<&String> {
-        match self {
-            TokenType::Identifier(ref value) => Some(value),
-            _ => None,
-        }
-    }
-}
-
-impl EnumExtractor<i64> for TokenType {
-    fn extract(&self) -> Option<&i64> {
-        match self {
-            TokenType::Integer(ref value) => Some(value),
-            _ => None,
-        }
-    }
-}
-
-impl Parser {
-    fn current(&self) -> Token {
-        self.tokens.get(self.idx).cloned().expect("Unexpected EOF")
-    }
-    fn peek(&self) -> Token {
-        self.tokens
-            .get(self.idx + 1)
-            .cloned()
-            .expect("Unexpected EOF")
-    }
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser {
-            program: Program { statements: vec![] },
-            tokens,
-            idx: 0,
-        }
-    }
-
-    fn increment(&mut self) {
-        self.idx += 1;
-    }
-
-    pub fn expect_with_value<T>(&mut self, expected: TokenType) -> Result<T, String>
-    where
-        TokenType: EnumExtractor<T>, // We are using the EnumExtractor trait to extract the value from the enum, only works for String and i64
-        T: Clone,                    // This is needed because we are returning a clone of the value
-    {
-        match self.current().token_type.extract() {
-            Some(inner)
-                if mem::discriminant(&self.current().token_type)
-                    == mem::discriminant(&expected) =>
-            {
-                self.increment();
-                Ok(inner.clone())
-            }
-            _ => Err(format!(
-                "Expected {:?}, got {:?} at index {}",
-                expected,
-                self.current().token_type,
-                self.current().index
-            )),
-        }
-    }
-
-    pub fn expect(&mut self, expected: TokenType) -> Result<(), String> {
-        // The mem::discriminant will disregard the value of the enum and only compare the enum-type
-        if mem::discriminant(&self.current().token_type) == mem::discriminant(&expected) {
-            self.increment();
-            Ok(())
-        } else {
-            Err(format!(
-                "Expected {:?}, got {:?} at index {}",
-                expected,
-                self.current().token_type,
-                self.current().index
-            ))
-        }
-    }
-
-    pub fn parse(&mut self) -> Result<Program, String> {
-        while self.idx < self.tokens.len() {
-            let stmt = self.parse_stmt()?;
-            self.program.statements.push(stmt);
-        }
-
-        Ok(self.program.clone())
-    }
-
-    fn parse_stmt(&mut self) -> Result<Stmt, String> {
-        match self.current().token_type {
-            TokenType::Identifier(i) => {
-                // Check if its a function call
-                if self.peek().token_type == TokenType::LeftParan {
-                    let expr = self.parse_expr()?;
-                    self.expect(TokenType::Pipe)?;
-                    Ok(Stmt::Assignment(AssignmentStmt {
-                        definition: false,
-                        name: "_".to_string(), // Assign to a temporary variable
-                        expr,
-                    }))
-                } else {
-                    let ident = i;
-                    self.increment();
-                    self.expect(TokenType::Assignment)?;
-                    let expr = self.parse_expr()?;
-                    self.expect(TokenType::Pipe)?;
-                    Ok(Stmt::Assignment(AssignmentStmt {
-                        definition: false,
-                        name: ident,
-                        expr,
-                    }))
-                }
-            }
-            TokenType::Return => {
-                self.increment();
-                if self.current().token_type == TokenType::Pipe {
-                    self.increment();
-                    Ok(Stmt::Return(Box::new(None)))
-                } else {
-                    let expr = self.parse_expr()?;
-                    self.expect(TokenType::Pipe)?;
-                    Ok(Stmt::Return(Box::new(Some(expr))))
-                }
-            }
-            TokenType::Keyword(k) => match k {
-                KeywordType::VarDef => {
-                    self.increment();
-                    let name =
-                        self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
-                    self.expect(TokenType::Assignment)?;
-                    let expr = self.parse_expr()?;
-                    self.expect(TokenType::Pipe)?;
-                    Ok(Stmt::Assignment(AssignmentStmt {
-                        definition: true,
-                        name,
-                        expr,
-                    }))
-                }
-                KeywordType::If => {
-                    self.increment();
-                    let condition: Expr = self.parse_expr()?;
-                    self.expect(TokenType::LeftBrack)?;
-
-                    let mut body = vec![];
-                    while self.current().token_type != TokenType::RightBrack {
-                        let stmt = self.parse_stmt()?;
-                        body.push(stmt);
-                    }
-                    self.expect(TokenType::RightBrack)?;
-
-                    let mut else_body = vec![];
-                    if self.current().token_type == TokenType::Keyword(KeywordType::Else) {
-                        self.increment();
-                        self.expect(TokenType::LeftBrack)?;
-
-                        while self.current().token_type != TokenType::RightBrack {
-                            let stmt = self.parse_stmt()?;
-                            else_body.push(stmt);
-                        }
-                        self.expect(TokenType::RightBrack)?;
-                    }
-
-                    Ok(Stmt::If(IfStmt {
-                        condition,
-                        body,
-                        else_body,
-                    }))
-                }
-                KeywordType::While => {
-                    self.increment();
-                    let condition = self.parse_expr()?;
-                    self.expect(TokenType::LeftBrack)?;
-
-                    let mut body = vec![];
-                    while self.current().token_type != TokenType::RightBrack {
-                        let stmt = self.parse_stmt()?;
-                        body.push(stmt);
-                    }
-                    self.expect(TokenType::RightBrack)?;
-
-                    Ok(Stmt::While(WhileStmt { condition, body }))
-                }
-                KeywordType::Function => {
-                    self.increment();
-                    let name =
-                        self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
-
-                    self.expect(TokenType::LeftParan)?;
-                    let mut params = vec![];
-                    while self.current().token_type != TokenType::RightParan {
-                        if self.current().token_type == TokenType::Comma {
-                            self.increment();
-                        }
-                        let param_name = self
-                            .expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
-                        params.push(param_name);
-                    }
-                    self.expect(TokenType::RightParan)?;
-
-                    self.expect(TokenType::LeftBrack)?;
-                    let mut body = vec![];
-                    while self.current().token_type != TokenType::RightBrack {
-                        let stmt = self.parse_stmt()?;
-                        body.push(stmt);
-                    }
-                    self.expect(TokenType::RightBrack)?;
-
-                    Ok(Stmt::Function(FunctionStmt { name, params, body }))
-                }
-                _ => Err(format!(
-                    "Unexpected keyword {:?} at index {}",
-                    k,
-                    self.current().index
-                )),
-            },
-            _ => Err(format!(
-                "Unexpected token {:?} at index {}",
-                self.current().token_type,
-                self.current().index
-            )),
-        }
-    }
-
-    //Precidence:
-    // 1. Parentheses
-    // 2. Equals and Not Equals
-    // 3. Multiplication and Division
-    // 4. Addition and Subtraction
-    fn parse_expr(&mut self) -> Result<Expr, String> {
-        // Start with the lowest precidence :)
-        self.parse_add_sub_expr()
-    }
-
-    fn parse_add_sub_expr(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_mul_div_expr()?;
-
-        while let Some(op) = self.parse_operator() {
-            match op {
-                OperatorType::Addition | OperatorType::Subtraction => {
-                    self.increment();
-                    let right = self.parse_mul_div_expr()?;
-                    left = Expr::Binary(Box::new(BinaryExpr { left, right, op }));
-                }
-                _ => break,
-            }
-        }
-
-        Ok(left)
-    }
-
-    fn parse_mul_div_expr(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_equals_expr()?;
-
-        while let Some(op) = self.parse_operator() {
-            match op {
-                OperatorType::Multiplication | OperatorType::Division => {
-                    self.increment();
-                    let right = self.parse_equals_expr()?;
-                    left = Expr::Binary(Box::new(BinaryExpr { left, right, op }));
-                }
-                _ => break,
-            }
-        }
-
-        Ok(left)
-    }
-
-    fn parse_equals_expr(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_primary_expr()?;
-
-        while let Some(op) = self.parse_operator() {
-            match op {
-                OperatorType::Equals | OperatorType::NotEquals => {
-                    self.increment();
-                    let right = self.parse_primary_expr()?;
-                    left = Expr::Binary(Box::new(BinaryExpr { left, right, op }));
-                }
-                _ => break,
-            }
-        }
-
-        Ok(left)
-    }
-
-    fn parse_primary_expr(&mut self) -> Result<Expr, String> {
-        match self.current().token_type {
-            TokenType::Integer(value) => {
-                self.increment();
-                Ok(Expr::Number(value))
-            }
-            TokenType::Identifier(ref name) => {
-                self.increment();
-                // Check if this is a function call
-                if self.current().token_type == TokenType::LeftParan {
-                    self.increment();
-                    let mut params = vec![];
-                    while self.current().token_type != TokenType::RightParan {
-                        params.push(self.parse_expr()?);
-                        if self.current().token_type == TokenType::Comma {
-                            self.increment();
-                        }
-                    }
-                    self.expect(TokenType::RightParan)?;
-                    Ok(Expr::Call(name.clone(), params))
-                } else {
-                    Ok(Expr::Variable(name.clone()))
-                }
-            }
-            TokenType::LeftParan => {
-                self.increment();
-                let expr = self.parse_expr()?;
-                self.expect(TokenType::RightParan)?;
-                Ok(expr)
-            }
-            _ => Err(format!(
-                "Unexpected token {:?} in expression at index {}",
-                self.current().token_type,
-                self.current().index
-            )),
-        }
-    }
-
-    fn parse_operator(&mut self) -> Option<OperatorType> {
-        match self.current().token_type {
-            TokenType::Operator(op) => Some(op),
-            _ => None,
-        }
-    }
-}
+use std::mem;
+use std::vec;
+
+use crate::diagnostics::Diagnostic;
+use crate::lexer::*;
+use crate::types::{StructId, Type};
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Binary(Box<BinaryExpr>),
+    Unary(Box<UnaryExpr>),
+    Array(Box<ArrayExpr>),
+    Index(Box<IndexExpr>),
+    Struct(Box<StructExpr>),
+    Field(Box<FieldExpr>),
+    Number(i64, Span),
+    Str(String, Span),
+    Bool(bool, Span),
+    Variable(String, Span),
+    Call(String, Vec<Expr>, Span),
+}
+
+impl Expr {
+    pub fn span(&self) -> &Span {
+        match self {
+            Expr::Binary(bin_expr) => &bin_expr.span,
+            Expr::Unary(unary_expr) => &unary_expr.span,
+            Expr::Array(array) => &array.span,
+            Expr::Index(index) => &index.span,
+            Expr::Struct(struct_expr) => &struct_expr.span,
+            Expr::Field(field) => &field.span,
+            Expr::Number(_, span)
+            | Expr::Str(_, span)
+            | Expr::Bool(_, span)
+            | Expr::Variable(_, span)
+            | Expr::Call(_, _, span) => span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BinaryExpr {
+    pub left: Expr,
+    pub right: Expr,
+    pub op: OperatorType,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnaryExpr {
+    pub op: OperatorType,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+// [a, b, c] or [value; len], where the length has to be a number
+#[derive(Debug, Clone)]
+pub struct ArrayExpr {
+    pub elements: Vec<Expr>,   // Just the value when it is repeated
+    pub repeat: Option<usize>, // The length of a [value; len]
+    pub span: Span,
+}
+
+// The compiled code keeps arrays on the stack, which only has a few megabytes
+pub const MAX_ARRAY_LEN: usize = 1 << 16;
+
+impl ArrayExpr {
+    pub fn len(&self) -> usize {
+        self.repeat.unwrap_or(self.elements.len())
+    }
+}
+
+// array[index], the array is always a variable
+#[derive(Debug, Clone)]
+pub struct IndexExpr {
+    pub array: String,
+    pub index: Expr,
+    pub span: Span,
+}
+
+// Point(x: 1, y: 2), every field has to be given but the order doesn't matter
+#[derive(Debug, Clone)]
+pub struct StructExpr {
+    pub id: StructId,
+    pub fields: Vec<FieldInit>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldInit {
+    pub name: String,
+    pub expr: Expr,
+    pub span: Span, // Just the name
+}
+
+// expr.field
+#[derive(Debug, Clone)]
+pub struct FieldExpr {
+    pub expr: Expr,
+    pub field: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Assignment(AssignmentStmt),
+    IndexAssignment(IndexAssignmentStmt),
+    FieldAssignment(FieldAssignmentStmt),
+    If(IfStmt),
+    While(WhileStmt),
+    For(ForStmt),
+    Function(FunctionStmt),
+    Return(Box<Option<Expr>>, Span),
+    Break(Span),
+    Continue(Span),
+}
+
+impl Stmt {
+    pub fn span(&self) -> &Span {
+        match self {
+            Stmt::Assignment(ass_stmt) => &ass_stmt.span,
+            Stmt::IndexAssignment(ass_stmt) => &ass_stmt.span,
+            Stmt::FieldAssignment(ass_stmt) => &ass_stmt.span,
+            Stmt::If(if_stmt) => &if_stmt.span,
+            Stmt::While(while_stmt) => &while_stmt.span,
+            Stmt::For(for_stmt) => &for_stmt.span,
+            Stmt::Function(func) => &func.span,
+            Stmt::Return(_, span) => span,
+            Stmt::Break(span) | Stmt::Continue(span) => span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssignmentStmt {
+    pub definition: bool,
+    pub name: String,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+// array[index] = expr|
+#[derive(Debug, Clone)]
+pub struct IndexAssignmentStmt {
+    pub array: String,
+    pub index: Expr,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+// var.field = expr|, the target always starts with a variable but can go through nested structs
+#[derive(Debug, Clone)]
+pub struct FieldAssignmentStmt {
+    pub target: FieldExpr,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct IfStmt {
+    pub branches: Vec<IfBranch>, // The sus and every sussy sus after it, tried in order
+    pub else_body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct IfBranch {
+    pub condition: Expr,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WhileStmt {
+    pub condition: Expr,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+// edge var in start..end >>, counts from start up to but not including end. The end is
+// evaluated once before the loop starts
+#[derive(Debug, Clone)]
+pub struct ForStmt {
+    pub var: String,
+    pub var_span: Span,
+    pub start: Expr,
+    pub end: Expr,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub typ: Option<Type>, // None when the type is left to inference
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionStmt {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub ret: Option<Type>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+// gang Name >> field: type, ... <<, only allowed at the top level
+#[derive(Debug, Clone)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub span: Span,
+}
+
+impl StructDecl {
+    pub fn field(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field.name == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub typ: Type,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    pub structs: Vec<StructDecl>, // A StructId is an index into this, in declaration order
+}
+
+pub struct Parser {
+    pub program: Program,
+    tokens: Vec<Token>,
+    idx: usize,
+    errors: Vec<Diagnostic>,
+}
+
+// This trait will extract the value from enums that have a value associated with them
+// For example, TokenType::Identifier("skibidi".to_string()) will be extracted to Some("skibidi")
+pub trait EnumExtractor<T> {
+    fn extract(&self) -> Option<&T>;
+}
+
+impl EnumExtractor<String> for TokenType {
+    fn extract(&self) -> Option<&String> {
+        match self {
+            TokenType::Identifier(ref value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl EnumExtractor<u64> for TokenType {
+    fn extract(&self) -> Option<&u64> {
+        match self {
+            TokenType::Integer(ref value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Parser {
+    // The lexer always ends the tokens with Eof, so past the end we just keep returning it
+    fn current(&self) -> Token {
+        self.tokens
+            .get(self.idx)
+            .or(self.tokens.last())
+            .cloned()
+            .expect("Lexer should always produce an Eof token")
+    }
+    fn peek(&self) -> Token {
+        self.tokens
+            .get(self.idx + 1)
+            .or(self.tokens.last())
+            .cloned()
+            .expect("Lexer should always produce an Eof token")
+    }
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            program: Program {
+                statements: vec![],
+                structs: vec![],
+            },
+            // Comments are kept by the lexer for tooling, the grammar never sees them
+            tokens: tokens
+                .into_iter()
+                .filter(|t| !t.token_type.is_trivia())
+                .collect(),
+            idx: 0,
+            errors: vec![],
+        }
+    }
+
+    fn increment(&mut self) {
+        self.idx += 1;
+    }
+
+    // Span from start up to and including the last consumed token
+    fn span_from(&self, start: &Span) -> Span {
+        match self.idx.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(prev) => start.to(&prev.span),
+            None => start.clone(),
+        }
+    }
+
+    fn unexpected(&self, expected: &TokenType) -> Diagnostic {
+        let found = self.current();
+        Diagnostic::error(
+            "E0100",
+            format!("expected {}, found {}", expected, found.token_type),
+        )
+        .with_label(&found.span, format!("expected {}", expected))
+    }
+
+    pub fn expect_with_value<T>(&mut self, expected: TokenType) -> Result<T, Diagnostic>
+    where
+        TokenType: EnumExtractor<T>, // We are using the EnumExtractor trait to extract the value from the enum, only works for String and i64
+        T: Clone,                    // This is needed because we are returning a clone of the value
+    {
+        match self.current().token_type.extract() {
+            Some(inner)
+                if mem::discriminant(&self.current().token_type)
+                    == mem::discriminant(&expected) =>
+            {
+                self.increment();
+                Ok(inner.clone())
+            }
+            _ => Err(self.unexpected(&expected)),
+        }
+    }
+
+    pub fn expect(&mut self, expected: TokenType) -> Result<(), Diagnostic> {
+        // The mem::discriminant will disregard the value of the enum and only compare the enum-type
+        if mem::discriminant(&self.current().token_type) == mem::discriminant(&expected) {
+            self.increment();
+            Ok(())
+        } else {
+            Err(self.unexpected(&expected))
+        }
+    }
+
+    // Parses the whole program, collecting every syntax error instead of stopping at the first
+    pub fn parse(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        while self.current().token_type != TokenType::Eof {
+            if self.current().token_type == TokenType::Keyword(KeywordType::Struct) {
+                match self.parse_struct() {
+                    Ok(decl) => self.program.structs.push(decl),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.synchronize();
+                    }
+                }
+                continue;
+            }
+            match self.parse_stmt() {
+                Ok(stmt) => self.program.statements.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                    // A stray << at the top level has no block to end
+                    if self.current().token_type == TokenType::RightBrack {
+                        self.increment();
+                    }
+                }
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(mem::take(&mut self.errors));
+        }
+
+        Ok(self.program.clone())
+    }
+
+    // Skips ahead to where the next statement should start after an error, which is after
+    // the next | or before the << that ends the current block. Nested >> << blocks are skipped whole
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.current().token_type {
+                TokenType::Eof => return,
+                TokenType::Pipe if depth == 0 => {
+                    self.increment();
+                    return;
+                }
+                TokenType::RightBrack if depth == 0 => return,
+                TokenType::LeftBrack => depth += 1,
+                TokenType::RightBrack => {
+                    depth -= 1;
+                    // The block ended the broken statement, unless a sussy block follows it
+                    if depth == 0 && self.peek().token_type != TokenType::Keyword(KeywordType::Else)
+                    {
+                        self.increment();
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            self.increment();
+        }
+    }
+
+    // Parses >> statements <<, recovering from errors in the statements inside
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, Diagnostic> {
+        let open = self.current().span;
+        self.expect(TokenType::LeftBrack)?;
+
+        let mut body = vec![];
+        while !matches!(
+            self.current().token_type,
+            TokenType::RightBrack | TokenType::Eof
+        ) {
+            match self.parse_stmt() {
+                Ok(stmt) => body.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
+            }
+        }
+
+        if self.current().token_type == TokenType::Eof {
+            return Err(Diagnostic::error("E0103", "unclosed block")
+                .with_label(
+                    &self.current().span,
+                    "expected `<<` before the end of the file",
+                )
+                .with_secondary_label(&open, "block opened here"));
+        }
+        self.increment();
+
+        Ok(body)
+    }
+
+    fn parse_stmt(&mut self) -> Result<Stmt, Diagnostic> {
+        let start = self.current().span;
+        match self.current().token_type {
+            TokenType::Identifier(i) => {
+                // Check if its a function call
+                if self.peek().token_type == TokenType::LeftParan {
+                    let expr = self.parse_expr()?;
+                    self.expect(TokenType::Pipe)?;
+                    Ok(Stmt::Assignment(AssignmentStmt {
+                        definition: false,
+                        name: "_".to_string(), // Assign to a temporary variable
+                        expr,
+                        span: self.span_from(&start),
+                    }))
+                } else if self.peek().token_type == TokenType::LeftSquare {
+                    self.increment();
+                    let index = self.parse_index()?;
+                    self.expect(TokenType::Assignment)?;
+                    let expr = self.parse_expr()?;
+                    self.expect(TokenType::Pipe)?;
+                    Ok(Stmt::IndexAssignment(IndexAssignmentStmt {
+                        array: i,
+                        index,
+                        expr,
+                        span: self.span_from(&start),
+                    }))
+                } else if self.peek().token_type == TokenType::Dot {
+                    let Expr::Field(target) = self.parse_postfix_expr()? else {
+                        unreachable!()
+                    };
+                    self.expect(TokenType::Assignment)?;
+                    let expr = self.parse_expr()?;
+                    self.expect(TokenType::Pipe)?;
+                    Ok(Stmt::FieldAssignment(FieldAssignmentStmt {
+                        target: *target,
+                        expr,
+                        span: self.span_from(&start),
+                    }))
+                } else {
+                    let ident = i;
+                    self.increment();
+                    self.expect(TokenType::Assignment)?;
+                    let expr = self.parse_expr()?;
+                    self.expect(TokenType::Pipe)?;
+                    Ok(Stmt::Assignment(AssignmentStmt {
+                        definition: false,
+                        name: ident,
+                        expr,
+                        span: self.span_from(&start),
+                    }))
+                }
+            }
+            TokenType::Return => {
+                self.increment();
+                if self.current().token_type == TokenType::Pipe {
+                    self.increment();
+                    Ok(Stmt::Return(Box::new(None), self.span_from(&start)))
+                } else {
+                    let expr = self.parse_expr()?;
+                    self.expect(TokenType::Pipe)?;
+                    Ok(Stmt::Return(Box::new(Some(expr)), self.span_from(&start)))
+                }
+            }
+            TokenType::Keyword(k) => match k {
+                KeywordType::VarDef => {
+                    self.increment();
+                    let name =
+                        self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+                    self.expect(TokenType::Assignment)?;
+                    let expr = self.parse_expr()?;
+                    self.expect(TokenType::Pipe)?;
+                    Ok(Stmt::Assignment(AssignmentStmt {
+                        definition: true,
+                        name,
+                        expr,
+                        span: self.span_from(&start),
+                    }))
+                }
+                KeywordType::If => {
+                    self.increment();
+                    let mut branches = vec![self.parse_if_branch()?];
+
+                    // sussy sus continues the chain, a plain sussy ends it
+                    let mut else_body = vec![];
+                    while self.current().token_type == TokenType::Keyword(KeywordType::Else) {
+                        self.increment();
+                        if self.current().token_type == TokenType::Keyword(KeywordType::If) {
+                            self.increment();
+                            branches.push(self.parse_if_branch()?);
+                        } else {
+                            else_body = self.parse_block()?;
+                            break;
+                        }
+                    }
+
+                    Ok(Stmt::If(IfStmt {
+                        branches,
+                        else_body,
+                        span: self.span_from(&start),
+                    }))
+                }
+                KeywordType::Break | KeywordType::Continue => {
+                    self.increment();
+                    self.expect(TokenType::Pipe)?;
+                    let span = self.span_from(&start);
+                    Ok(match k {
+                        KeywordType::Break => Stmt::Break(span),
+                        _ => Stmt::Continue(span),
+                    })
+                }
+                KeywordType::While => {
+                    self.increment();
+                    if matches!(self.current().token_type, TokenType::Identifier(_))
+                        && self.peek().token_type == TokenType::Keyword(KeywordType::In)
+                    {
+                        return self.parse_for(&start);
+                    }
+
+                    let condition = self.parse_expr()?;
+                    let body = self.parse_block()?;
+
+                    Ok(Stmt::While(WhileStmt {
+                        condition,
+                        body,
+                        span: self.span_from(&start),
+                    }))
+                }
+                KeywordType::Function => {
+                    self.increment();
+                    let name =
+                        self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+
+                    self.expect(TokenType::LeftParan)?;
+                    let mut params = vec![];
+                    while self.current().token_type != TokenType::RightParan {
+                        if self.current().token_type == TokenType::Comma {
+                            self.increment();
+                        }
+                        let param_start = self.current().span;
+                        let name = self
+                            .expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+                        let typ = self.parse_annotation()?;
+                        params.push(Param {
+                            name,
+                            typ,
+                            span: self.span_from(&param_start),
+                        });
+                    }
+                    self.expect(TokenType::RightParan)?;
+                    let ret = self.parse_annotation()?;
+                    let body = self.parse_block()?;
+
+                    Ok(Stmt::Function(FunctionStmt {
+                        name,
+                        params,
+                        ret,
+                        body,
+                        span: self.span_from(&start),
+                    }))
+                }
+                KeywordType::Struct => Err(Diagnostic::error(
+                    "E0107",
+                    "structs can only be declared at the top level",
+                )
+                .with_label(&start, "inside a block")),
+                _ => Err(Diagnostic::error(
+                    "E0101",
+                    format!(
+                        "unexpected {} at the start of a statement",
+                        TokenType::Keyword(k)
+                    ),
+                )
+                .with_label(&start, "expected a statement")
+                .with_note("`sussy` has to directly follow the `<<` of a `sus` block")),
+            },
+            t => Err(Diagnostic::error(
+                "E0101",
+                format!("unexpected {} at the start of a statement", t),
+            )
+            .with_label(&start, "expected a statement")),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, Diagnostic> {
+        self.parse_binary_expr(0)
+    }
+
+    // Precedence climbing: parses operands and every operator that binds tighter than min
+    fn parse_binary_expr(&mut self, min: u8) -> Result<Expr, Diagnostic> {
+        let mut left = self.parse_prefix_expr()?;
+        // The precedence of the last non-associative operator, they can't be chained
+        let mut chained: Option<(u8, Span)> = None;
+
+        while let Some(op) = self.parse_operator() {
+            let Some((precedence, assoc)) = binary_operator(op) else {
+                break;
+            };
+            if precedence < min {
+                break;
+            }
+
+            let op_span = self.current().span;
+            if assoc == Assoc::None {
+                if let Some((_, first)) = chained.as_ref().filter(|(p, _)| *p == precedence) {
+                    return Err(Diagnostic::error(
+                        "E0105",
+                        "comparison operators can't be chained",
+                    )
+                    .with_label(&op_span, "second comparison")
+                    .with_secondary_label(first, "first comparison")
+                    .with_note("use parentheses to say which comparison comes first"));
+                }
+                chained = Some((precedence, op_span));
+            }
+
+            self.increment();
+            let right = self.parse_binary_expr(precedence + 1)?;
+            let span = left.span().to(right.span());
+            left = Expr::Binary(Box::new(BinaryExpr {
+                left,
+                right,
+                op,
+                span,
+            }));
+        }
+
+        Ok(left)
+    }
+
+    // A prefix operator applies to everything after it that binds tighter than it does
+    fn parse_prefix_expr(&mut self) -> Result<Expr, Diagnostic> {
+        let start = self.current().span;
+        let Some((op, precedence)) = self
+            .parse_operator()
+            .and_then(|op| Some((op, prefix_operator(op)?)))
+        else {
+            return self.parse_postfix_expr();
+        };
+
+        self.increment();
+        // The smallest int is the only literal whose magnitude doesn't fit in an int
+        if let (OperatorType::Negate, TokenType::Integer(value)) = (op, &self.current().token_type)
+        {
+            if *value == i64::MIN.unsigned_abs() {
+                let span = start.to(&self.current().span);
+                self.increment();
+                return Ok(Expr::Number(i64::MIN, span));
+            }
+        }
+        let expr = self.parse_binary_expr(precedence)?;
+        let span = start.to(expr.span());
+        // A minus in front of a number is part of the literal rather than an operator
+        if let (OperatorType::Negate, Expr::Number(num, _)) = (op, &expr) {
+            return Ok(Expr::Number(num.wrapping_neg(), span));
+        }
+        Ok(Expr::Unary(Box::new(UnaryExpr { op, expr, span })))
+    }
+
+    // Field accesses bind tighter than any operator
+    fn parse_postfix_expr(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.parse_primary_expr()?;
+        while self.current().token_type == TokenType::Dot {
+            self.increment();
+            let field = self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+            let span = self.span_from(expr.span());
+            expr = Expr::Field(Box::new(FieldExpr { expr, field, span }));
+        }
+        Ok(expr)
+    }
+
+    fn parse_struct(&mut self) -> Result<StructDecl, Diagnostic> {
+        let start = self.current().span;
+        self.increment();
+        let name = self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+
+        self.expect(TokenType::LeftBrack)?;
+        let mut fields = vec![];
+        while self.current().token_type != TokenType::RightBrack {
+            let field_start = self.current().span;
+            let name = self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+            self.expect(TokenType::Colon)?;
+            let typ = self.parse_type()?;
+            fields.push(Field {
+                name,
+                typ,
+                span: self.span_from(&field_start),
+            });
+            if self.current().token_type != TokenType::Comma {
+                break;
+            }
+            self.increment();
+        }
+        if fields.is_empty() {
+            return Err(
+                Diagnostic::error("E0106", "structs can't be empty").with_label(
+                    &start.to(&self.current().span),
+                    "a struct needs at least one field",
+                ),
+            );
+        }
+        self.expect(TokenType::RightBrack)?;
+
+        Ok(StructDecl {
+            name,
+            fields,
+            span: self.span_from(&start),
+        })
+    }
+
+    // The field: value pairs of a struct literal, after the name
+    fn parse_struct_expr(&mut self, id: StructId, start: &Span) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::LeftParan)?;
+        let mut fields = vec![];
+        while self.current().token_type != TokenType::RightParan {
+            let span = self.current().span;
+            let name = self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+            self.expect(TokenType::Colon)?;
+            let expr = self.parse_expr()?;
+            fields.push(FieldInit { name, expr, span });
+            if self.current().token_type != TokenType::Comma {
+                break;
+            }
+            self.increment();
+        }
+        self.expect(TokenType::RightParan)?;
+
+        Ok(Expr::Struct(Box::new(StructExpr {
+            id,
+            fields,
+            span: self.span_from(start),
+        })))
+    }
+
+    // The rest of a counted loop, after the edge
+    fn parse_for(&mut self, start: &Span) -> Result<Stmt, Diagnostic> {
+        let var_span = self.current().span;
+        let var = self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+        self.expect(TokenType::Keyword(KeywordType::In))?;
+        let from = self.parse_expr()?;
+        self.expect(TokenType::DotDot)?;
+        let end = self.parse_expr()?;
+        let body = self.parse_block()?;
+
+        Ok(Stmt::For(ForStmt {
+            var,
+            var_span,
+            start: from,
+            end,
+            body,
+            span: self.span_from(start),
+        }))
+    }
+
+    // The [index] after an array name
+    fn parse_index(&mut self) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::LeftSquare)?;
+        let index = self.parse_expr()?;
+        self.expect(TokenType::RightSquare)?;
+        Ok(index)
+    }
+
+    // The condition and block after a sus or sussy sus
+    fn parse_if_branch(&mut self) -> Result<IfBranch, Diagnostic> {
+        let condition = self.parse_expr()?;
+        let body = self.parse_block()?;
+        Ok(IfBranch { condition, body })
+    }
+
+    // An optional `: type` after a parameter or a parameter list
+    fn parse_annotation(&mut self) -> Result<Option<Type>, Diagnostic> {
+        if self.current().token_type != TokenType::Colon {
+            return Ok(None);
+        }
+        self.increment();
+        Ok(Some(self.parse_type()?))
+    }
+
+    // Structs have to be declared before they are used, so they can't contain themselves
+    fn parse_type(&mut self) -> Result<Type, Diagnostic> {
+        let start = self.current().span;
+        let name = self.expect_with_value::<String>(TokenType::Identifier("".to_string()))?;
+        match name.as_str() {
+            "int" => Ok(Type::Int),
+            "bool" => Ok(Type::Bool),
+            "string" => Ok(Type::Str),
+            _ => match self.struct_id(&name) {
+                Some(id) => Ok(Type::Struct(id)),
+                None => Err(
+                    Diagnostic::error("E0104", format!("unknown type `{}`", name))
+                        .with_label(&start, "not a type")
+                        .with_note(
+                            "the types are `int`, `bool`, `string` and structs declared above with `gang`",
+                        ),
+                ),
+            },
+        }
+    }
+
+    fn struct_id(&self, name: &str) -> Option<StructId> {
+        self.program
+            .structs
+            .iter()
+            .position(|decl| decl.name == name)
+    }
+
+    fn parse_primary_expr(&mut self) -> Result<Expr, Diagnostic> {
+        let start = self.current().span;
+        match self.current().token_type {
+            TokenType::Integer(value) => {
+                self.increment();
+                match i64::try_from(value) {
+                    Ok(value) => Ok(Expr::Number(value, start)),
+                    Err(_) => Err(literal_too_large(&start)),
+                }
+            }
+            TokenType::String(ref value) => {
+                self.increment();
+                Ok(Expr::Str(value.clone(), start))
+            }
+            TokenType::Boolean(value) => {
+                self.increment();
+                Ok(Expr::Bool(value, start))
+            }
+            TokenType::Identifier(ref name) => {
+                self.increment();
+                // Check if this is a function call, or a struct literal which looks like one
+                if self.current().token_type == TokenType::LeftParan {
+                    if let Some(id) = self.struct_id(name) {
+                        return self.parse_struct_expr(id, &start);
+                    }
+                    self.increment();
+                    let mut params = vec![];
+                    while self.current().token_type != TokenType::RightParan {
+                        params.push(self.parse_expr()?);
+                        if self.current().token_type != TokenType::Comma {
+                            break;
+                        }
+                        self.increment();
+                    }
+                    self.expect(TokenType::RightParan)?;
+                    Ok(Expr::Call(name.clone(), params, self.span_from(&start)))
+                } else if self.current().token_type == TokenType::LeftSquare {
+                    let index = self.parse_index()?;
+                    Ok(Expr::Index(Box::new(IndexExpr {
+                        array: name.clone(),
+                        index,
+                        span: self.span_from(&start),
+                    })))
+                } else {
+                    Ok(Expr::Variable(name.clone(), start))
+                }
+            }
+            TokenType::LeftSquare => {
+                self.increment();
+                if self.current().token_type == TokenType::RightSquare {
+                    return Err(
+                        Diagnostic::error("E0108", "arrays can't be empty").with_label(
+                            &start.to(&self.current().span),
+                            "an array needs at least one element",
+                        ),
+                    );
+                }
+                let mut elements = vec![self.parse_expr()?];
+                let mut repeat = None;
+                if self.current().token_type == TokenType::Semicolon {
+                    self.increment();
+                    let len_span = self.current().span;
+                    let len = self.expect_with_value::<u64>(TokenType::Integer(0))?;
+                    if len == 0 {
+                        return Err(Diagnostic::error("E0108", "arrays can't be empty")
+                            .with_label(&len_span, "the length has to be at least 1"));
+                    }
+                    if len > MAX_ARRAY_LEN as u64 {
+                        return Err(array_too_long(&len_span));
+                    }
+                    repeat = Some(len as usize);
+                } else {
+                    while self.current().token_type == TokenType::Comma {
+                        self.increment();
+                        elements.push(self.parse_expr()?);
+                    }
+                    if elements.len() > MAX_ARRAY_LEN {
+                        return Err(array_too_long(&self.span_from(&start)));
+                    }
+                }
+                self.expect(TokenType::RightSquare)?;
+                Ok(Expr::Array(Box::new(ArrayExpr {
+                    elements,
+                    repeat,
+                    span: self.span_from(&start),
+                })))
+            }
+            TokenType::LeftParan => {
+                self.increment();
+                let expr = self.parse_expr()?;
+                self.expect(TokenType::RightParan)?;
+                Ok(expr)
+            }
+            t => Err(
+                Diagnostic::error("E0102", format!("expected an expression, found {}", t))
+                    .with_label(&start, "expected an expression"),
+            ),
+        }
+    }
+
+    fn parse_operator(&mut self) -> Option<OperatorType> {
+        match self.current().token_type {
+            TokenType::Operator(op) => Some(op),
+            _ => None,
+        }
+    }
+}
+
+fn array_too_long(span: &Span) -> Diagnostic {
+    Diagnostic::error("E0109", "array is too long")
+        .with_label(span, format!("the length can be at most {}", MAX_ARRAY_LEN))
+        .with_note("arrays are kept on the stack, which only has room for so many ints")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    None, // `a == b == c` is an error instead of silently comparing a bool
+}
+
+// Precedence and associativity of every binary operator, higher binds tighter. A new operator
+// only needs a line here. Unlike in C the bitwise operators bind tighter than the comparisons,
+// so `x bitand 1 == 0` means what it looks like
+fn binary_operator(op: OperatorType) -> Option<(u8, Assoc)> {
+    match op {
+        OperatorType::Or => Some((1, Assoc::Left)),
+        OperatorType::And => Some((2, Assoc::Left)),
+        OperatorType::Equals | OperatorType::NotEquals => Some((4, Assoc::None)),
+        OperatorType::Less
+        | OperatorType::Greater
+        | OperatorType::LessEquals
+        | OperatorType::GreaterEquals => Some((5, Assoc::None)),
+        OperatorType::BitOr => Some((6, Assoc::Left)),
+        OperatorType::BitXor => Some((7, Assoc::Left)),
+        OperatorType::BitAnd => Some((8, Assoc::Left)),
+        OperatorType::ShiftLeft | OperatorType::ShiftRight => Some((9, Assoc::Left)),
+        OperatorType::Addition | OperatorType::Subtraction => Some((10, Assoc::Left)),
+        OperatorType::Multiplication | OperatorType::Division | OperatorType::Modulo => {
+            Some((11, Assoc::Left))
+        }
+        OperatorType::Not | OperatorType::Negate | OperatorType::BitNot => None,
+    }
+}
+
+// Precedence of the operand of every prefix operator. `not` sits below the comparisons so
+// `not a == b` negates the whole comparison, while `-` and `bitnot` bind tighter than any binary
+// operator
+fn prefix_operator(op: OperatorType) -> Option<u8> {
+    match op {
+        OperatorType::Not => Some(3),
+        OperatorType::Negate | OperatorType::BitNot => Some(12),
+        _ => None,
+    }
+}
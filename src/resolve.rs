@@ -0,0 +1,565 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::lexer::Span;
+use crate::parser::*;
+
+// Works out which definition every name in the program refers to, so that backends can lift
+// nested functions to the top level. Every function gets a unique name, and variables from
+// enclosing functions that a function uses (directly or through the functions it calls) become
+// explicit captures. Assumes the program already passed semantic analysis.
+
+pub type FuncId = usize;
+pub type BindingId = usize;
+
+// The top level statements are treated as a function of their own
+pub const MAIN: FuncId = 0;
+
+// Names the generated code can't use for its variables: C keywords, including the ones GNU C and
+// C23 add, and what the generated C calls from inside functions. Function names get a prefix in
+// the backends instead
+const RESERVED: &[&str] = &[
+    "_",
+    "main",
+    "print",
+    "print_str",
+    "printf",
+    "puts",
+    "putchar",
+    "exit",
+    "abort",
+    "alignas",
+    "alignof",
+    "asm",
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "char",
+    "const",
+    "constexpr",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "nullptr",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "struct",
+    "switch",
+    "thread_local",
+    "true",
+    "typedef",
+    "typeof",
+    "typeof_unqual",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+];
+
+#[derive(Debug, Clone)]
+struct Binding {
+    name: String,
+    owner: Option<FuncId>,  // None for the global _
+    declared: Option<Span>, // The definition, parameter or loop counter that introduces it
+    defined: Option<Span>,  // Where it gets its value, it can be used once this has ended
+}
+
+#[derive(Debug, Clone)]
+struct CallSite {
+    name: String,
+    span: Span,
+    ctx: FuncId,
+    callee: FuncId,
+}
+
+// A call that runs before a variable the callee uses has been defined. That can only happen by
+// calling a function before its definition
+#[derive(Debug, Clone)]
+pub struct EarlyCall {
+    pub name: String,
+    pub span: Span,
+    pub var: String,
+    pub defined: Span,
+}
+
+// A definition that is never read or called, names starting with _ don't count
+#[derive(Debug, Clone)]
+pub enum Unused {
+    Variable(String, Span),
+    Param(String, Span),
+    Function(String, Span),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedFunction {
+    pub name: String,
+    pub params: Vec<BindingId>,
+    pub captures: Vec<BindingId>,
+    pub locals: Vec<BindingId>, // Params, captures and then the variables defined inside
+    parent: FuncId,
+    uses: BTreeSet<BindingId>,
+    calls: BTreeSet<FuncId>,
+    names: HashMap<BindingId, String>, // What each binding is called inside this function
+    source: Option<(String, Span)>,    // The name and definition in the source, None for main
+}
+
+// How a variable is reached from inside a function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarKind {
+    Global,
+    Local,
+    Captured, // Captured variables are passed as pointers
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub id: BindingId,
+    pub name: String,
+    pub kind: VarKind,
+}
+
+#[derive(Debug, Clone)]
+struct Scope {
+    vars: HashMap<String, BindingId>,
+    funcs: HashMap<String, FuncId>,
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    functions: Vec<ResolvedFunction>,
+    bindings: Vec<Binding>,
+    defs: HashMap<usize, FuncId>,    // FunctionStmt span start
+    vars: HashMap<usize, BindingId>, // Variable and AssignmentStmt span start
+    calls: HashMap<usize, FuncId>,   // Call span start, builtins are missing
+    call_sites: Vec<CallSite>,
+    reads: HashSet<BindingId>, // Assigning to a variable doesn't count as using it
+}
+
+impl Resolution {
+    pub fn new(program: &Program) -> Self {
+        let mut res = Resolution::default();
+        res.bindings.push(Binding {
+            name: "_".to_string(),
+            owner: None,
+            declared: None,
+            defined: None,
+        });
+        res.functions.push(ResolvedFunction {
+            name: "main".to_string(),
+            ..Default::default()
+        });
+
+        let scope = Scope {
+            vars: HashMap::from([("_".to_string(), 0)]),
+            funcs: HashMap::new(),
+        };
+        res.resolve_stmts(&program.statements, scope, MAIN);
+        res.find_captures();
+        res.assign_names();
+        res
+    }
+
+    pub fn function(&self, func: &FunctionStmt) -> FuncId {
+        self.defs[&func.span.start]
+    }
+
+    pub fn info(&self, id: FuncId) -> &ResolvedFunction {
+        &self.functions[id]
+    }
+
+    // The function a call refers to, None for built-ins like print
+    pub fn callee(&self, span: &Span) -> Option<FuncId> {
+        self.calls.get(&span.start).copied()
+    }
+
+    // The variable read by an Expr::Variable or written by an AssignmentStmt at span. The same
+    // goes for the array of an IndexExpr or IndexAssignmentStmt, the counter of a ForStmt is at
+    // its var_span and the hidden end at its span
+    pub fn variable(&self, ctx: FuncId, span: &Span) -> Variable {
+        self.binding(ctx, self.vars[&span.start])
+    }
+
+    // Every call that can run before a variable its callee captures is defined. A function that
+    // captures a variable of the caller gets it from the caller, so it has to be defined before
+    // the call. Captures of the caller's own captures were checked where the caller is called
+    pub fn early_calls(&self) -> Vec<EarlyCall> {
+        let mut early = vec![];
+        for site in &self.call_sites {
+            for &binding in &self.functions[site.callee].captures {
+                let binding = &self.bindings[binding];
+                let Some(defined) = &binding.defined else {
+                    continue;
+                };
+                if binding.owner == Some(site.ctx) && defined.end > site.span.start {
+                    early.push(EarlyCall {
+                        name: site.name.clone(),
+                        span: site.span.clone(),
+                        var: binding.name.clone(),
+                        defined: defined.clone(),
+                    });
+                }
+            }
+        }
+        early
+    }
+
+    // Every variable, parameter and function that is never used, in the order they are defined.
+    // A function counts as used when the top level can reach a call to it, so functions that only
+    // call each other are unused too. Functions starting with _ are kept on purpose, so what they
+    // call counts as used
+    pub fn unused(&self) -> Vec<Unused> {
+        let mut unused = vec![];
+        for (id, binding) in self.bindings.iter().enumerate() {
+            let (Some(owner), Some(declared)) = (binding.owner, &binding.declared) else {
+                continue;
+            };
+            if binding.name.starts_with('_') || self.reads.contains(&id) {
+                continue;
+            }
+            let name = binding.name.clone();
+            if self.functions[owner].params.contains(&id) {
+                unused.push(Unused::Param(name, declared.clone()));
+            } else {
+                unused.push(Unused::Variable(name, declared.clone()));
+            }
+        }
+        let mut reached = HashSet::new();
+        let mut stack: Vec<FuncId> = (0..self.functions.len())
+            .filter(|&id| match &self.functions[id].source {
+                Some((name, _)) => name.starts_with('_'),
+                None => true,
+            })
+            .collect();
+        while let Some(id) = stack.pop() {
+            if reached.insert(id) {
+                let sites = self.call_sites.iter().filter(|site| site.ctx == id);
+                stack.extend(sites.map(|site| site.callee));
+            }
+        }
+        for (id, func) in self.functions.iter().enumerate() {
+            let Some((name, span)) = &func.source else {
+                continue;
+            };
+            if !reached.contains(&id) {
+                unused.push(Unused::Function(name.clone(), span.clone()));
+            }
+        }
+        unused.sort_by_key(|unused| match unused {
+            Unused::Variable(_, span) | Unused::Param(_, span) | Unused::Function(_, span) => {
+                span.start
+            }
+        });
+        unused
+    }
+
+    pub fn binding(&self, ctx: FuncId, binding: BindingId) -> Variable {
+        if self.bindings[binding].owner.is_none() {
+            return Variable {
+                id: binding,
+                name: self.bindings[binding].name.clone(),
+                kind: VarKind::Global,
+            };
+        }
+
+        let func = &self.functions[ctx];
+        Variable {
+            id: binding,
+            name: func.names[&binding].clone(),
+            kind: if func.captures.contains(&binding) {
+                VarKind::Captured
+            } else {
+                VarKind::Local
+            },
+        }
+    }
+
+    fn bind(&mut self, name: &str, owner: FuncId, declared: &Span, defined: &Span) -> BindingId {
+        self.bindings.push(Binding {
+            name: name.to_string(),
+            owner: Some(owner),
+            declared: Some(declared.clone()),
+            defined: Some(defined.clone()),
+        });
+        self.bindings.len() - 1
+    }
+
+    fn use_binding(&mut self, ctx: FuncId, span: &Span, binding: BindingId) {
+        self.vars.insert(span.start, binding);
+        self.functions[ctx].uses.insert(binding);
+    }
+
+    // Functions are visible in the whole scope they are defined in, so they get their ids before
+    // anything in the scope is resolved
+    fn declare_functions(&mut self, stmts: &[Stmt], scope: &mut Scope, ctx: FuncId) {
+        for stmt in stmts {
+            if let Stmt::Function(func) = stmt {
+                let id = self.functions.len();
+                self.functions.push(ResolvedFunction {
+                    name: func.name.clone(),
+                    parent: ctx,
+                    source: Some((func.name.clone(), func.span.clone())),
+                    ..Default::default()
+                });
+                self.defs.insert(func.span.start, id);
+                scope.funcs.insert(func.name.clone(), id);
+            }
+        }
+    }
+
+    fn resolve_stmts(&mut self, stmts: &[Stmt], scope: Scope, ctx: FuncId) {
+        let mut scope = scope;
+        self.declare_functions(stmts, &mut scope, ctx);
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assignment(ass_stmt) => {
+                    self.resolve_expr(&ass_stmt.expr, &scope, ctx);
+                    if ass_stmt.definition {
+                        let binding =
+                            self.bind(&ass_stmt.name, ctx, &ass_stmt.span, &ass_stmt.span);
+                        scope.vars.insert(ass_stmt.name.clone(), binding);
+                    }
+                    let binding = scope.vars[&ass_stmt.name];
+                    self.use_binding(ctx, &ass_stmt.span, binding);
+                }
+                Stmt::IndexAssignment(ass_stmt) => {
+                    self.resolve_expr(&ass_stmt.index, &scope, ctx);
+                    self.resolve_expr(&ass_stmt.expr, &scope, ctx);
+                    self.use_binding(ctx, &ass_stmt.span, scope.vars[&ass_stmt.array]);
+                }
+                Stmt::FieldAssignment(ass_stmt) => {
+                    self.resolve_expr(&ass_stmt.target.expr, &scope, ctx);
+                    self.resolve_expr(&ass_stmt.expr, &scope, ctx);
+                }
+                Stmt::Function(func) => {
+                    let id = self.defs[&func.span.start];
+                    let mut new_scope = scope.clone();
+                    for param in &func.params {
+                        let binding = self.bind(&param.name, id, &param.span, &param.span);
+                        self.functions[id].params.push(binding);
+                        new_scope.vars.insert(param.name.clone(), binding);
+                    }
+
+                    self.resolve_stmts(&func.body, new_scope, id);
+                }
+                Stmt::If(if_stmt) => {
+                    for branch in &if_stmt.branches {
+                        self.resolve_expr(&branch.condition, &scope, ctx);
+                        self.resolve_stmts(&branch.body, scope.clone(), ctx);
+                    }
+                    self.resolve_stmts(&if_stmt.else_body, scope.clone(), ctx);
+                }
+                Stmt::Return(expr, _) => {
+                    if let Some(expr) = expr.as_ref() {
+                        self.resolve_expr(expr, &scope, ctx);
+                    }
+                }
+                Stmt::While(while_stmt) => {
+                    self.resolve_expr(&while_stmt.condition, &scope, ctx);
+                    self.resolve_stmts(&while_stmt.body, scope.clone(), ctx);
+                }
+                Stmt::For(for_stmt) => {
+                    self.resolve_expr(&for_stmt.start, &scope, ctx);
+                    self.resolve_expr(&for_stmt.end, &scope, ctx);
+
+                    // The end is kept in a hidden variable so it is only evaluated once, it's
+                    // found through the span of the whole loop
+                    let end = self.bind("_end", ctx, &for_stmt.span, for_stmt.end.span());
+                    self.use_binding(ctx, &for_stmt.span, end);
+
+                    // The counter is set once the range has been evaluated
+                    let mut body_scope = scope.clone();
+                    let defined = for_stmt.var_span.to(for_stmt.end.span());
+                    let binding = self.bind(&for_stmt.var, ctx, &for_stmt.var_span, &defined);
+                    body_scope.vars.insert(for_stmt.var.clone(), binding);
+                    self.use_binding(ctx, &for_stmt.var_span, binding);
+                    self.resolve_stmts(&for_stmt.body, body_scope, ctx);
+                }
+                Stmt::Break(_) | Stmt::Continue(_) => {}
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr, scope: &Scope, ctx: FuncId) {
+        match expr {
+            Expr::Binary(bin_expr) => {
+                self.resolve_expr(&bin_expr.left, scope, ctx);
+                self.resolve_expr(&bin_expr.right, scope, ctx);
+            }
+            Expr::Unary(unary_expr) => self.resolve_expr(&unary_expr.expr, scope, ctx),
+            Expr::Call(name, params, span) => {
+                if let Some(&id) = scope.funcs.get(name) {
+                    self.calls.insert(span.start, id);
+                    self.functions[ctx].calls.insert(id);
+                    self.call_sites.push(CallSite {
+                        name: name.clone(),
+                        span: span.clone(),
+                        ctx,
+                        callee: id,
+                    });
+                }
+                for arg in params {
+                    self.resolve_expr(arg, scope, ctx);
+                }
+            }
+            Expr::Variable(name, span) => {
+                self.use_binding(ctx, span, scope.vars[name]);
+                self.reads.insert(scope.vars[name]);
+            }
+            Expr::Array(array) => {
+                for element in &array.elements {
+                    self.resolve_expr(element, scope, ctx);
+                }
+            }
+            Expr::Index(index) => {
+                self.resolve_expr(&index.index, scope, ctx);
+                self.use_binding(ctx, &index.span, scope.vars[&index.array]);
+                self.reads.insert(scope.vars[&index.array]);
+            }
+            Expr::Struct(struct_expr) => {
+                for init in &struct_expr.fields {
+                    self.resolve_expr(&init.expr, scope, ctx);
+                }
+            }
+            Expr::Field(field) => self.resolve_expr(&field.expr, scope, ctx),
+            Expr::Number(..) | Expr::Str(..) | Expr::Bool(..) => {}
+        }
+    }
+
+    // A function captures every outer variable it uses, plus the ones that the functions it
+    // calls capture, since it has to pass them along. Repeats until nothing changes
+    fn find_captures(&mut self) {
+        let mut captures: Vec<BTreeSet<BindingId>> = self
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(id, func)| {
+                func.uses
+                    .iter()
+                    .copied()
+                    .filter(|&b| self.is_outer(b, id))
+                    .collect()
+            })
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for id in 0..self.functions.len() {
+                for &callee in &self.functions[id].calls {
+                    let needed: Vec<BindingId> = captures[callee]
+                        .iter()
+                        .copied()
+                        .filter(|&b| self.is_outer(b, id) && !captures[id].contains(&b))
+                        .collect();
+                    changed |= !needed.is_empty();
+                    captures[id].extend(needed);
+                }
+            }
+        }
+
+        for (func, captures) in self.functions.iter_mut().zip(captures) {
+            func.captures = captures.into_iter().collect();
+        }
+    }
+
+    fn is_outer(&self, binding: BindingId, func: FuncId) -> bool {
+        self.bindings[binding]
+            .owner
+            .is_some_and(|owner| owner != func)
+    }
+
+    // Functions get names that are unique in the whole program, nested ones are prefixed with
+    // the function they are defined in. Variables only have to be unique inside their function
+    fn assign_names(&mut self) {
+        let mut taken: HashSet<String> = RESERVED.iter().map(|s| s.to_string()).collect();
+        for id in 1..self.functions.len() {
+            let parent = self.functions[id].parent;
+            let base = if parent == MAIN {
+                self.functions[id].name.clone()
+            } else {
+                format!(
+                    "{}_{}",
+                    self.functions[parent].name, self.functions[id].name
+                )
+            };
+            self.functions[id].name = fresh(&mut taken, &base);
+        }
+
+        for id in 0..self.functions.len() {
+            let mut local_taken = taken.clone();
+            let func = &self.functions[id];
+
+            let owned = (0..self.bindings.len())
+                .filter(|&b| self.bindings[b].owner == Some(id) && !func.params.contains(&b));
+            let bindings: Vec<BindingId> = func
+                .params
+                .iter()
+                .chain(func.captures.iter())
+                .copied()
+                .chain(owned)
+                .collect();
+
+            let names = bindings
+                .iter()
+                .map(|&b| (b, fresh(&mut local_taken, &self.bindings[b].name)))
+                .collect();
+            self.functions[id].names = names;
+            self.functions[id].locals = bindings;
+        }
+    }
+}
+
+// User functions get a prefix in both backends, so they can't clash with the C library, like
+// remove from stdio.h, or with registers like rax in assembly. The runtime starts with rt_ instead
+pub fn symbol(name: &str) -> String {
+    format!("skl_{}", name)
+}
+
+// C names for struct tags or the fields of one struct. Those have namespaces of their own in C,
+// so they only have to stay clear of the reserved names and each other
+pub fn c_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut taken: HashSet<String> = RESERVED.iter().map(|s| s.to_string()).collect();
+    names.map(|name| fresh(&mut taken, name)).collect()
+}
+
+// Returns base, or base with the lowest numbered suffix that isn't taken yet. C reserves a lot of
+// the names that start with an underscore, so leading ones become u_ instead of being dropped,
+// which would turn _EOF into the EOF macro. Names starting like the runtime's or the prefixed
+// function names get u_ in front too, so a variable can't hide them
+fn fresh(taken: &mut HashSet<String>, base: &str) -> String {
+    let trimmed = base.trim_start_matches('_');
+    let base = if trimmed.len() < base.len() {
+        format!("u_{}", trimmed)
+    } else if base.starts_with("rt_") || base.starts_with("skl_") {
+        format!("u_{}", base)
+    } else {
+        base.to_string()
+    };
+    let mut name = base.clone();
+    let mut i = 1;
+    while taken.contains(&name) {
+        name = format!("{}_{}", base, i);
+        i += 1;
+    }
+    taken.insert(name.clone());
+    name
+}
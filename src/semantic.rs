@@ -1,127 +1,470 @@
-use std::collections::HashMap;
-
-use crate::parser::*;
-
-#[derive(Debug, Clone)]
-struct Scope {
-    vars: Vec<String>,
-    funcs: HashMap<String, usize>, // Name and number of parameters
-}
-
-pub struct Semantic {
-    program: Program,
-}
-
-impl Semantic {
-    pub fn new(program: Program) -> Self {
-        Self { program }
-    }
-    pub fn analyze(&self) -> Result<(), String> {
-        let mut scope = Scope {
-            vars: vec!["_".to_string()],
-            funcs: HashMap::new(),
-        };
-        // Add built-in functions
-        scope.funcs.insert("print".to_string(), 1);
-
-        self.analyze_stmts(self.program.statements.clone(), scope)?;
-        Ok(())
-    }
-
-    // Very ugly i know
-    fn func_exists(&self, scope: &Scope, name: String) -> bool {
-        scope
-            .funcs
-            .keys()
-            .cloned()
-            .collect::<Vec<String>>()
-            .contains(&name)
-    }
-
-    fn ident_exists(&self, scope: &Scope, name: String) -> bool {
-        self.func_exists(scope, name.clone()) || scope.vars.contains(&name)
-    }
-
-    fn analyze_stmts(&self, stmts: Vec<Stmt>, scope: Scope) -> Result<(), String> {
-        let mut scope = scope;
-        for stmt in stmts {
-            match stmt {
-                Stmt::Assignment(ass_stmt) => {
-                    if self.func_exists(&scope, ass_stmt.name.clone()) {
-                        return Err(format!(
-                            "Identifier {} already defined as function",
-                            ass_stmt.name
-                        ));
-                    }
-
-                    if ass_stmt.definition {
-                        scope.vars.push(ass_stmt.name);
-                    } else if !scope.vars.contains(&ass_stmt.name) {
-                        return Err(format!("Variable {} not defined", &ass_stmt.name));
-                    }
-
-                    self.analyze_expr(ass_stmt.expr, &scope)?;
-                }
-                Stmt::Function(func) => {
-                    if self.ident_exists(&scope, func.name.clone()) {
-                        return Err(format!("Identifier {} already defined", func.name));
-                    }
-                    scope.funcs.insert(func.name.clone(), func.params.len());
-
-                    let mut new_scope = scope.clone();
-                    new_scope.vars.extend(func.params.clone());
-
-                    self.analyze_stmts(func.body, new_scope)?;
-                }
-                Stmt::If(if_stmt) => {
-                    self.analyze_expr(if_stmt.condition, &scope)?;
-                    self.analyze_stmts(if_stmt.body, scope.clone())?;
-                    self.analyze_stmts(if_stmt.else_body, scope.clone())?;
-                }
-                Stmt::Return(expr) => {
-                    if expr.is_some() {
-                        self.analyze_expr(expr.unwrap(), &scope)?;
-                    }
-                }
-                Stmt::While(while_stmt) => {
-                    self.analyze_expr(while_stmt.condition, &scope)?;
-                    self.analyze_stmts(while_stmt.body, scope.clone())?;
-                }
-            }
-        }
-        Ok(())
-    }
-
-    fn analyze_expr(&self, expr: Expr, scope: &Scope) -> Result<(), String> {
-        match expr {
-            Expr::Binary(bin_expr) => {
-                self.analyze_expr(bin_expr.left, scope)?;
-                self.analyze_expr(bin_expr.right, scope)?;
-            }
-            Expr::Call(name, params) => {
-                if !self.func_exists(scope, name.clone()) {
-                    return Err(format!("Function {} not defined", name));
-                }
-                if !scope.funcs.get(&name).unwrap().eq(&params.len()) {
-                    return Err(format!(
-                        "Function {} expects {} parameters, got {}",
-                        name,
-                        scope.funcs.get(&name).unwrap(),
-                        params.len()
-                    ));
-                }
-
-                for arg in params {
-                    self.analyze_expr(arg, scope)?;
-                }
-            }
-            Expr::Variable(name) => {
-                if !scope.vars.contains(&name) {
-                    return Err(format!("Variable {} not defined", name));
-                }
-            }
-            _ => {}
-        }
-        Ok(())
-    }
-}
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::lexer::{OperatorType, Span};
+use crate::parser::*;
+use crate::resolve::{Resolution, Unused};
+use crate::types::{always_returns, returns_value};
+
+#[derive(Debug, Clone)]
+struct Scope {
+    vars: Vec<String>,
+    funcs: HashMap<String, usize>,     // Name and number of parameters
+    func_spans: HashMap<String, Span>, // Where user defined functions are defined
+    in_loop: bool,                     // Whether yeet and bet have a loop to jump out of
+    in_function: bool,                 // Whether sigma has a function to return from
+}
+
+pub struct Semantic {
+    program: Program,
+    warnings: RefCell<Vec<Diagnostic>>,
+}
+
+impl Semantic {
+    pub fn new(program: Program) -> Self {
+        Self {
+            program,
+            warnings: RefCell::new(vec![]),
+        }
+    }
+
+    // Returns what every name refers to and the warnings when the program is fine
+    pub fn analyze(&self) -> Result<(Resolution, Vec<Diagnostic>), Diagnostic> {
+        let mut scope = Scope {
+            vars: vec!["_".to_string()],
+            funcs: HashMap::new(),
+            func_spans: HashMap::new(),
+            in_loop: false,
+            in_function: false,
+        };
+        // Add built-in functions
+        scope.funcs.insert("print".to_string(), 1);
+        scope.funcs.insert("len".to_string(), 1);
+
+        self.analyze_structs()?;
+        self.analyze_stmts(self.program.statements.clone(), scope)?;
+
+        // Resolving names needs a program where every name is defined
+        let resolution = Resolution::new(&self.program);
+        self.analyze_call_order(&resolution)?;
+        self.analyze_unused(&resolution);
+        Ok((resolution, self.warnings.take()))
+    }
+
+    // Functions can be called before they are defined, but not before the variables they use
+    fn analyze_call_order(&self, resolution: &Resolution) -> Result<(), Diagnostic> {
+        match resolution.early_calls().first() {
+            Some(call) => Err(Diagnostic::error(
+                "E0220",
+                format!("`{}` is called before `{}` is defined", call.name, call.var),
+            )
+            .with_label(&call.span, format!("`{}` uses `{}`", call.name, call.var))
+            .with_secondary_label(&call.defined, format!("`{}` is defined here", call.var))),
+            None => Ok(()),
+        }
+    }
+
+    fn analyze_unused(&self, resolution: &Resolution) {
+        for unused in resolution.unused() {
+            let (code, what, name, span) = match unused {
+                Unused::Variable(name, span) => ("W0201", "variable", name, span),
+                Unused::Param(name, span) => ("W0201", "parameter", name, span),
+                Unused::Function(name, span) => ("W0202", "function", name, span),
+            };
+            self.warnings.borrow_mut().push(
+                Diagnostic::warning(code, format!("unused {} `{}`", what, name))
+                    .with_label(&span, format!("`{}` is never used", name))
+                    .with_note(format!("rename it to `_{}` if that's on purpose", name)),
+            );
+        }
+    }
+
+    fn analyze_structs(&self) -> Result<(), Diagnostic> {
+        for (i, decl) in self.program.structs.iter().enumerate() {
+            if let Some(first) = self.program.structs[..i]
+                .iter()
+                .find(|other| other.name == decl.name)
+            {
+                return Err(Diagnostic::error(
+                    "E0202",
+                    format!("`{}` is already defined", decl.name),
+                )
+                .with_label(&decl.span, "redefined here")
+                .with_secondary_label(&first.span, "defined here"));
+            }
+            for (j, field) in decl.fields.iter().enumerate() {
+                if let Some(first) = decl.fields[..j].iter().find(|f| f.name == field.name) {
+                    return Err(Diagnostic::error(
+                        "E0215",
+                        format!("field `{}` is already declared", field.name),
+                    )
+                    .with_label(&field.span, "declared again here")
+                    .with_secondary_label(&first.span, "first declared here"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn struct_named(&self, name: &str) -> Option<&StructDecl> {
+        self.program.structs.iter().find(|decl| decl.name == name)
+    }
+
+    // Very ugly i know
+    fn func_exists(&self, scope: &Scope, name: String) -> bool {
+        scope
+            .funcs
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>()
+            .contains(&name)
+    }
+
+    fn ident_exists(&self, scope: &Scope, name: String) -> bool {
+        self.func_exists(scope, name.clone()) || scope.vars.contains(&name)
+    }
+
+    // Points at where the function is defined, if it isn't a built-in
+    fn with_definition(&self, diagnostic: Diagnostic, scope: &Scope, name: &str) -> Diagnostic {
+        match scope.func_spans.get(name) {
+            Some(span) => diagnostic.with_secondary_label(span, "defined here"),
+            None => diagnostic,
+        }
+    }
+
+    // Functions can be called anywhere in the scope they are defined in, so they are all declared
+    // before any statement is analyzed
+    fn declare_functions(&self, stmts: &[Stmt], scope: &mut Scope) -> Result<(), Diagnostic> {
+        for stmt in stmts {
+            let Stmt::Function(func) = stmt else {
+                continue;
+            };
+            // Calling it would look just like building the struct
+            if let Some(decl) = self.struct_named(&func.name) {
+                return Err(Diagnostic::error(
+                    "E0202",
+                    format!("`{}` is already defined as a struct", func.name),
+                )
+                .with_label(&func.span, "redefined here")
+                .with_secondary_label(&decl.span, "struct defined here"));
+            }
+            if self.ident_exists(scope, func.name.clone()) {
+                return Err(self.with_definition(
+                    Diagnostic::error("E0202", format!("`{}` is already defined", func.name))
+                        .with_label(&func.span, "redefined here"),
+                    scope,
+                    &func.name,
+                ));
+            }
+            scope.funcs.insert(func.name.clone(), func.params.len());
+            scope
+                .func_spans
+                .insert(func.name.clone(), func.span.clone());
+        }
+        Ok(())
+    }
+
+    fn analyze_stmts(&self, stmts: Vec<Stmt>, scope: Scope) -> Result<(), Diagnostic> {
+        let mut scope = scope;
+        self.declare_functions(&stmts, &mut scope)?;
+        // The statement that always returns, and whether what follows has been warned about
+        let mut returned: Option<(Span, bool)> = None;
+        for stmt in stmts {
+            // Functions after a sigma can still be called, so they aren't unreachable
+            if let Some((span, warned @ false)) = &mut returned {
+                if !matches!(stmt, Stmt::Function(_)) {
+                    self.warnings.borrow_mut().push(
+                        Diagnostic::warning("W0200", "unreachable statement")
+                            .with_label(stmt.span(), "unreachable statement")
+                            .with_secondary_label(span, "any code following this is unreachable"),
+                    );
+                    *warned = true;
+                }
+            }
+            if returned.is_none() && always_returns(std::slice::from_ref(&stmt)) {
+                returned = Some((stmt.span().clone(), false));
+            }
+
+            match stmt {
+                Stmt::Assignment(ass_stmt) => {
+                    if self.func_exists(&scope, ass_stmt.name.clone()) {
+                        return Err(self.with_definition(
+                            Diagnostic::error(
+                                "E0200",
+                                format!("`{}` is already defined as a function", ass_stmt.name),
+                            )
+                            .with_label(&ass_stmt.span, "cannot be used as a variable"),
+                            &scope,
+                            &ass_stmt.name,
+                        ));
+                    }
+
+                    // The new variable isn't visible in its own initializer
+                    self.analyze_expr(ass_stmt.expr, &scope)?;
+
+                    if ass_stmt.definition {
+                        scope.vars.push(ass_stmt.name);
+                    } else if !scope.vars.contains(&ass_stmt.name) {
+                        return Err(Diagnostic::error(
+                            "E0201",
+                            format!("variable `{}` is not defined", ass_stmt.name),
+                        )
+                        .with_label(&ass_stmt.span, "not defined in this scope")
+                        .with_note(format!(
+                            "use `looksmaxxing {} = ...|` to define a new variable",
+                            ass_stmt.name
+                        )));
+                    }
+                }
+                Stmt::IndexAssignment(ass_stmt) => {
+                    self.check_defined(&scope, &ass_stmt.array, &ass_stmt.span)?;
+                    self.analyze_expr(ass_stmt.index, &scope)?;
+                    self.analyze_expr(ass_stmt.expr, &scope)?;
+                }
+                Stmt::FieldAssignment(ass_stmt) => {
+                    self.analyze_expr(ass_stmt.target.expr, &scope)?;
+                    self.analyze_expr(ass_stmt.expr, &scope)?;
+                }
+                Stmt::Function(func) => {
+                    // A loop around the definition doesn't count inside the body
+                    let mut new_scope = scope.clone();
+                    new_scope
+                        .vars
+                        .extend(func.params.iter().map(|p| p.name.clone()));
+                    new_scope.in_loop = false;
+                    new_scope.in_function = true;
+
+                    // Falling off the end or a bare sigma is only fine for functions that don't
+                    // return anything
+                    if func.ret.is_some() || returns_value(&func.body) {
+                        if !always_returns(&func.body) {
+                            return Err(missing_value(&func)
+                                .with_label(&func.span, "can reach the end of its body")
+                                .with_note(
+                                    "every path through `sus`, `sussy` and `edge` has to end with `sigma`",
+                                ));
+                        }
+                        if let Some(span) = bare_return(&func.body) {
+                            return Err(missing_value(&func)
+                                .with_label(span, "`sigma` without a value")
+                                .with_secondary_label(&func.span, "in this function")
+                                .with_note("write `sigma <value>|` here"));
+                        }
+                    }
+
+                    self.analyze_stmts(func.body, new_scope)?;
+                }
+                Stmt::If(if_stmt) => {
+                    for branch in if_stmt.branches {
+                        self.analyze_expr(branch.condition, &scope)?;
+                        self.analyze_stmts(branch.body, scope.clone())?;
+                    }
+                    self.analyze_stmts(if_stmt.else_body, scope.clone())?;
+                }
+                Stmt::Return(_, span) if !scope.in_function => {
+                    return Err(Diagnostic::error("E0221", "`sigma` outside of a function")
+                        .with_label(&span, "not inside a `skibidi` function"));
+                }
+                Stmt::Return(expr, _) => {
+                    if expr.is_some() {
+                        self.analyze_expr(expr.unwrap(), &scope)?;
+                    }
+                }
+                Stmt::While(while_stmt) => {
+                    self.analyze_expr(while_stmt.condition, &scope)?;
+                    let mut body_scope = scope.clone();
+                    body_scope.in_loop = true;
+                    self.analyze_stmts(while_stmt.body, body_scope)?;
+                }
+                Stmt::For(for_stmt) => {
+                    if self.func_exists(&scope, for_stmt.var.clone()) {
+                        return Err(self.with_definition(
+                            Diagnostic::error(
+                                "E0200",
+                                format!("`{}` is already defined as a function", for_stmt.var),
+                            )
+                            .with_label(&for_stmt.var_span, "cannot be used as a variable"),
+                            &scope,
+                            &for_stmt.var,
+                        ));
+                    }
+                    self.analyze_expr(for_stmt.start, &scope)?;
+                    self.analyze_expr(for_stmt.end, &scope)?;
+
+                    // The counter only exists inside the loop
+                    let mut body_scope = scope.clone();
+                    body_scope.vars.push(for_stmt.var);
+                    body_scope.in_loop = true;
+                    self.analyze_stmts(for_stmt.body, body_scope)?;
+                }
+                Stmt::Break(span) if !scope.in_loop => return Err(outside_loop("yeet", &span)),
+                Stmt::Continue(span) if !scope.in_loop => return Err(outside_loop("bet", &span)),
+                Stmt::Break(_) | Stmt::Continue(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn analyze_expr(&self, expr: Expr, scope: &Scope) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Binary(bin_expr) => {
+                // Amounts that are known up front have to fit in the 64 bits of an int
+                if let (
+                    OperatorType::ShiftLeft | OperatorType::ShiftRight,
+                    Expr::Number(amount, span),
+                ) = (bin_expr.op, &bin_expr.right)
+                {
+                    if !(0..64).contains(amount) {
+                        return Err(Diagnostic::error(
+                            "E0209",
+                            format!("shift amount {} is out of range", amount),
+                        )
+                        .with_label(span, "not between 0 and 63")
+                        .with_note("an int is 64 bits wide"));
+                    }
+                }
+
+                if let (OperatorType::Division | OperatorType::Modulo, Expr::Number(0, span)) =
+                    (bin_expr.op, &bin_expr.right)
+                {
+                    return Err(Diagnostic::error("E0219", "attempt to divide by zero")
+                        .with_label(span, "this is always zero"));
+                }
+
+                self.analyze_expr(bin_expr.left, scope)?;
+                self.analyze_expr(bin_expr.right, scope)?;
+            }
+            Expr::Unary(unary_expr) => self.analyze_expr(unary_expr.expr, scope)?,
+            Expr::Call(name, params, span) => {
+                if !self.func_exists(scope, name.clone()) {
+                    return Err(Diagnostic::error(
+                        "E0203",
+                        format!("function `{}` is not defined", name),
+                    )
+                    .with_label(&span, "not defined in this scope"));
+                }
+                if !scope.funcs.get(&name).unwrap().eq(&params.len()) {
+                    return Err(self.with_definition(
+                        Diagnostic::error(
+                            "E0204",
+                            format!(
+                                "function `{}` expects {} parameters, got {}",
+                                name,
+                                scope.funcs.get(&name).unwrap(),
+                                params.len()
+                            ),
+                        )
+                        .with_label(&span, "wrong number of arguments"),
+                        scope,
+                        &name,
+                    ));
+                }
+
+                for arg in params {
+                    self.analyze_expr(arg, scope)?;
+                }
+            }
+            Expr::Variable(name, span) => self.check_defined(scope, &name, &span)?,
+            Expr::Array(array) => {
+                for element in array.elements {
+                    self.analyze_expr(element, scope)?;
+                }
+            }
+            Expr::Index(index) => {
+                self.check_defined(scope, &index.array, &index.span)?;
+                self.analyze_expr(index.index, scope)?;
+            }
+            Expr::Struct(struct_expr) => {
+                let decl = &self.program.structs[struct_expr.id];
+                for (i, init) in struct_expr.fields.iter().enumerate() {
+                    if decl.field(&init.name).is_none() {
+                        return Err(Diagnostic::error(
+                            "E0214",
+                            format!("struct `{}` has no field `{}`", decl.name, init.name),
+                        )
+                        .with_label(&init.span, "unknown field")
+                        .with_secondary_label(&decl.span, "struct declared here"));
+                    }
+                    if let Some(first) = struct_expr.fields[..i]
+                        .iter()
+                        .find(|other| other.name == init.name)
+                    {
+                        return Err(Diagnostic::error(
+                            "E0215",
+                            format!("field `{}` is already given", init.name),
+                        )
+                        .with_label(&init.span, "given again here")
+                        .with_secondary_label(&first.span, "first given here"));
+                    }
+                }
+
+                let missing: Vec<String> = decl
+                    .fields
+                    .iter()
+                    .filter(|field| !struct_expr.fields.iter().any(|f| f.name == field.name))
+                    .map(|field| format!("`{}`", field.name))
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(Diagnostic::error(
+                        "E0216",
+                        format!(
+                            "missing fields {} for struct `{}`",
+                            missing.join(", "),
+                            decl.name
+                        ),
+                    )
+                    .with_label(&struct_expr.span, "every field has to be given")
+                    .with_secondary_label(&decl.span, "struct declared here"));
+                }
+
+                for init in struct_expr.fields {
+                    self.analyze_expr(init.expr, scope)?;
+                }
+            }
+            Expr::Field(field) => self.analyze_expr(field.expr, scope)?,
+            Expr::Number(..) | Expr::Str(..) | Expr::Bool(..) => {}
+        }
+        Ok(())
+    }
+
+    fn check_defined(&self, scope: &Scope, name: &str, span: &Span) -> Result<(), Diagnostic> {
+        if scope.vars.iter().any(|var| var == name) {
+            return Ok(());
+        }
+        Err(
+            Diagnostic::error("E0201", format!("variable `{}` is not defined", name))
+                .with_label(span, "not defined in this scope"),
+        )
+    }
+}
+
+fn missing_value(func: &FunctionStmt) -> Diagnostic {
+    Diagnostic::error(
+        "E0222",
+        format!(
+            "function `{}` doesn't return a value on every path",
+            func.name
+        ),
+    )
+}
+
+// The first sigma without a value in the function body, not counting nested functions
+fn bare_return(stmts: &[Stmt]) -> Option<&Span> {
+    stmts.iter().find_map(|stmt| match stmt {
+        Stmt::Return(expr, span) if expr.is_none() => Some(span),
+        Stmt::If(if_stmt) => if_stmt
+            .branches
+            .iter()
+            .find_map(|branch| bare_return(&branch.body))
+            .or_else(|| bare_return(&if_stmt.else_body)),
+        Stmt::While(while_stmt) => bare_return(&while_stmt.body),
+        Stmt::For(for_stmt) => bare_return(&for_stmt.body),
+        _ => None,
+    })
+}
+
+fn outside_loop(keyword: &str, span: &Span) -> Diagnostic {
+    Diagnostic::error("E0210", format!("`{}` outside of a loop", keyword))
+        .with_label(span, "not inside an `edge` loop")
+}
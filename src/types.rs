@@ -0,0 +1,611 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::lexer::{Span, TokenType};
+use crate::resolve::*;
+use crate::{parser::*, OperatorType};
+
+// Infers and checks the type of every expression, variable and function. Parameters and return
+// types without annotations start out unknown and are unified with whatever they are used as,
+// anything that is still unknown at the end is an int. Runs once after semantic analysis, using
+// its Resolution, and the backends are handed the result along with it.
+
+// Index into Program::structs
+pub type StructId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Bool,
+    Str,
+    Array(usize), // Fixed length array of ints
+    Struct(StructId),
+}
+
+impl Type {
+    // How the type is written in the source, structs need the program for their name
+    pub fn name(&self, program: &Program) -> String {
+        match self {
+            Type::Int => "int".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Str => "string".to_string(),
+            Type::Array(len) => format!("[int; {}]", len),
+            Type::Struct(id) => program.structs[*id].name.clone(),
+        }
+    }
+}
+
+// A type that might not be inferred yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Known(Type),
+    Var(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum VarState {
+    Unknown,
+    Known(Type),
+    Same(usize), // Unified with another variable
+}
+
+#[derive(Debug, Default)]
+pub struct Types {
+    bindings: HashMap<BindingId, Type>,
+    returns: HashMap<FuncId, Type>,
+    exprs: HashMap<(usize, usize), Type>, // Expression span start and end
+}
+
+impl Types {
+    pub fn binding(&self, id: BindingId) -> Type {
+        self.bindings.get(&id).copied().unwrap_or(Type::Int)
+    }
+
+    pub fn returns(&self, id: FuncId) -> Type {
+        self.returns.get(&id).copied().unwrap_or(Type::Int)
+    }
+
+    pub fn of(&self, expr: &Expr) -> Type {
+        let span = expr.span();
+        self.exprs[&(span.start, span.end)]
+    }
+}
+
+pub struct TypeChecker<'a> {
+    program: &'a Program,
+    resolution: &'a Resolution,
+    vars: Vec<VarState>,
+    bindings: HashMap<BindingId, Ty>,
+    returns: HashMap<FuncId, Ty>,
+    exprs: HashMap<(usize, usize), Ty>,
+    // Checked at the end, once the operand types are known
+    comparisons: Vec<(Ty, Span)>,
+    prints: Vec<(Ty, Span)>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(program: &'a Program, resolution: &'a Resolution) -> Self {
+        Self {
+            program,
+            resolution,
+            vars: vec![],
+            bindings: HashMap::new(),
+            returns: HashMap::new(),
+            exprs: HashMap::new(),
+            comparisons: vec![],
+            prints: vec![],
+        }
+    }
+
+    pub fn check(&mut self) -> Result<Types, Diagnostic> {
+        self.check_stmts(&self.program.statements, MAIN)?;
+
+        for (ty, span) in std::mem::take(&mut self.comparisons) {
+            match self.find(ty) {
+                Ty::Known(Type::Str) => {
+                    return Err(Diagnostic::error("E0207", "strings can't be compared")
+                        .with_label(&span, "comparing two strings")
+                        .with_note("only `int` and `bool` values can be compared"));
+                }
+                Ty::Known(typ @ Type::Struct(_)) => {
+                    return Err(Diagnostic::error("E0207", "structs can't be compared")
+                        .with_label(&span, format!("comparing two `{}`", typ.name(self.program)))
+                        .with_note("compare the fields one by one"));
+                }
+                _ => {}
+            }
+        }
+        for (ty, span) in std::mem::take(&mut self.prints) {
+            if let Ty::Known(typ @ Type::Struct(_)) = self.find(ty) {
+                return Err(Diagnostic::error("E0218", "structs can't be printed")
+                    .with_label(&span, format!("this is a `{}`", typ.name(self.program)))
+                    .with_note("print the fields one by one"));
+            }
+        }
+
+        Ok(Types {
+            bindings: self.finish(&self.bindings),
+            returns: self.finish(&self.returns),
+            exprs: self.finish(&self.exprs),
+        })
+    }
+
+    fn finish<K: Copy + Eq + std::hash::Hash>(&self, types: &HashMap<K, Ty>) -> HashMap<K, Type> {
+        types
+            .iter()
+            .map(|(&k, &ty)| match self.find(ty) {
+                Ty::Known(typ) => (k, typ),
+                Ty::Var(_) => (k, Type::Int),
+            })
+            .collect()
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.vars.push(VarState::Unknown);
+        Ty::Var(self.vars.len() - 1)
+    }
+
+    fn binding(&mut self, id: BindingId) -> Ty {
+        match self.bindings.get(&id) {
+            Some(&ty) => ty,
+            None => {
+                let ty = self.fresh();
+                self.bindings.insert(id, ty);
+                ty
+            }
+        }
+    }
+
+    fn ret(&mut self, id: FuncId) -> Ty {
+        match self.returns.get(&id) {
+            Some(&ty) => ty,
+            None => {
+                let ty = self.fresh();
+                self.returns.insert(id, ty);
+                ty
+            }
+        }
+    }
+
+    // The known type, or the variable that stands for everything unified with ty
+    fn find(&self, ty: Ty) -> Ty {
+        let Ty::Var(mut var) = ty else {
+            return ty;
+        };
+        loop {
+            match self.vars[var] {
+                VarState::Unknown => return Ty::Var(var),
+                VarState::Known(typ) => return Ty::Known(typ),
+                VarState::Same(next) => var = next,
+            }
+        }
+    }
+
+    fn unify(&mut self, expected: Ty, found: Ty) -> Result<(), (Type, Type)> {
+        match (self.find(expected), self.find(found)) {
+            (Ty::Known(a), Ty::Known(b)) if a == b => Ok(()),
+            (Ty::Known(a), Ty::Known(b)) => Err((a, b)),
+            (Ty::Var(var), Ty::Known(typ)) | (Ty::Known(typ), Ty::Var(var)) => {
+                self.vars[var] = VarState::Known(typ);
+                Ok(())
+            }
+            (Ty::Var(a), Ty::Var(b)) => {
+                if a != b {
+                    self.vars[a] = VarState::Same(b);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: Ty, found: Ty, span: &Span) -> Result<(), Diagnostic> {
+        self.unify(expected, found).map_err(|(expected, found)| {
+            Diagnostic::error("E0206", "mismatched types").with_label(
+                span,
+                format!(
+                    "expected `{}`, found `{}`",
+                    expected.name(self.program),
+                    found.name(self.program)
+                ),
+            )
+        })
+    }
+
+    // The annotated signatures of the functions in a scope are known before any of them is called,
+    // so a mismatched argument is reported at the call even when it comes before the definition
+    fn declare_functions(&mut self, stmts: &[Stmt]) -> Result<(), Diagnostic> {
+        for stmt in stmts {
+            let Stmt::Function(func) = stmt else {
+                continue;
+            };
+            let id = self.resolution.function(func);
+            let params = self.resolution.info(id).params.clone();
+            for (param, binding) in func.params.iter().zip(params) {
+                if let Some(typ) = param.typ {
+                    let ty = self.binding(binding);
+                    self.expect(ty, Ty::Known(typ), &param.span)?;
+                }
+            }
+            let ret = self.ret(id);
+            if let Some(typ) = func.ret {
+                self.expect(ret, Ty::Known(typ), &func.span)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_stmts(&mut self, stmts: &[Stmt], ctx: FuncId) -> Result<(), Diagnostic> {
+        self.declare_functions(stmts)?;
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assignment(ass_stmt) => {
+                    // Array literals can only be used to define a variable
+                    let found = match &ass_stmt.expr {
+                        Expr::Array(array) if ass_stmt.definition => {
+                            self.check_array_literal(array, ctx)?
+                        }
+                        expr => self.check_expr(expr, ctx)?,
+                    };
+                    let var = self.resolution.variable(ctx, &ass_stmt.span);
+                    // Call statements assign to _, which takes anything
+                    if var.kind == VarKind::Global {
+                        continue;
+                    }
+                    let expected = self.binding(var.id);
+                    self.expect(expected, found, ass_stmt.expr.span())
+                        .map_err(|err| {
+                            err.with_note("variables keep the type they were defined with")
+                        })?;
+                }
+                Stmt::IndexAssignment(ass_stmt) => {
+                    let len = self.array_len(ctx, &ass_stmt.array, &ass_stmt.span)?;
+                    self.check_index(&ass_stmt.index, len, ctx)?;
+                    let found = self.check_expr(&ass_stmt.expr, ctx)?;
+                    self.expect(Ty::Known(Type::Int), found, ass_stmt.expr.span())
+                        .map_err(|err| err.with_note("arrays hold `int`"))?;
+                }
+                Stmt::FieldAssignment(ass_stmt) => {
+                    let expected = self.check_field(&ass_stmt.target, ctx)?;
+                    let found = self.check_expr(&ass_stmt.expr, ctx)?;
+                    self.expect(expected, found, ass_stmt.expr.span())?;
+                }
+                Stmt::Function(func) => {
+                    let id = self.resolution.function(func);
+                    let ret = self.ret(id);
+                    self.check_stmts(&func.body, id)?;
+
+                    // Semantic analysis made sure that functions returning a value do so on every
+                    // path, so this is about using one that never does as something else
+                    if !returns_value(&func.body) {
+                        self.unify(ret, Ty::Known(Type::Int)).map_err(|(typ, _)| {
+                            Diagnostic::error(
+                                "E0208",
+                                format!(
+                                    "function `{}` doesn't return a value but is used as `{}`",
+                                    func.name,
+                                    typ.name(self.program)
+                                ),
+                            )
+                            .with_label(&func.span, "no `sigma` with a value")
+                            .with_note("a function that doesn't return a value gives the integer 0")
+                        })?;
+                    }
+                }
+                Stmt::If(if_stmt) => {
+                    for branch in &if_stmt.branches {
+                        self.check_condition(&branch.condition, ctx)?;
+                        self.check_stmts(&branch.body, ctx)?;
+                    }
+                    self.check_stmts(&if_stmt.else_body, ctx)?;
+                }
+                Stmt::Return(expr, span) => {
+                    let (found, span) = match expr.as_ref() {
+                        Some(expr) => (self.check_expr(expr, ctx)?, expr.span()),
+                        None => (Ty::Known(Type::Int), span),
+                    };
+                    let expected = self.ret(ctx);
+                    self.expect(expected, found, span)?;
+                }
+                Stmt::While(while_stmt) => {
+                    self.check_condition(&while_stmt.condition, ctx)?;
+                    self.check_stmts(&while_stmt.body, ctx)?;
+                }
+                Stmt::For(for_stmt) => {
+                    for bound in [&for_stmt.start, &for_stmt.end] {
+                        let found = self.check_expr(bound, ctx)?;
+                        self.expect(Ty::Known(Type::Int), found, bound.span())
+                            .map_err(|err| err.with_note("loops can only count over `int`"))?;
+                    }
+                    for span in [&for_stmt.var_span, &for_stmt.span] {
+                        let var = self.resolution.variable(ctx, span);
+                        let ty = self.binding(var.id);
+                        self.expect(ty, Ty::Known(Type::Int), span)?;
+                    }
+                    self.check_stmts(&for_stmt.body, ctx)?;
+                }
+                Stmt::Break(_) | Stmt::Continue(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_condition(&mut self, condition: &Expr, ctx: FuncId) -> Result<(), Diagnostic> {
+        let found = self.check_expr(condition, ctx)?;
+        self.expect_condition(found, condition)
+    }
+
+    // The operands of `and`, `or` and `not` are conditions too
+    fn expect_condition(&mut self, found: Ty, condition: &Expr) -> Result<(), Diagnostic> {
+        self.expect(Ty::Known(Type::Bool), found, condition.span())
+            .map_err(|err| err.with_note("conditions have to be `bool`"))
+    }
+
+    fn check_array_literal(&mut self, array: &ArrayExpr, ctx: FuncId) -> Result<Ty, Diagnostic> {
+        for element in &array.elements {
+            let found = self.check_expr(element, ctx)?;
+            self.expect(Ty::Known(Type::Int), found, element.span())
+                .map_err(|err| err.with_note("arrays hold `int`"))?;
+        }
+        let ty = Ty::Known(Type::Array(array.len()));
+        self.exprs.insert((array.span.start, array.span.end), ty);
+        Ok(ty)
+    }
+
+    // The length of the array variable at span
+    fn array_len(&mut self, ctx: FuncId, name: &str, span: &Span) -> Result<usize, Diagnostic> {
+        let var = self.resolution.variable(ctx, span);
+        let ty = self.binding(var.id);
+        match self.find(ty) {
+            Ty::Known(Type::Array(len)) => Ok(len),
+            ty => {
+                let found = match ty {
+                    Ty::Known(typ) => typ,
+                    Ty::Var(_) => Type::Int,
+                };
+                Err(
+                    Diagnostic::error("E0212", format!("`{}` is not an array", name)).with_label(
+                        span,
+                        format!("expected an array, found `{}`", found.name(self.program)),
+                    ),
+                )
+            }
+        }
+    }
+
+    // Indices have to be ints, and constant ones have to be in bounds
+    fn check_index(&mut self, index: &Expr, len: usize, ctx: FuncId) -> Result<(), Diagnostic> {
+        let found = self.check_expr(index, ctx)?;
+        self.expect(Ty::Known(Type::Int), found, index.span())
+            .map_err(|err| err.with_note("indices have to be `int`"))?;
+
+        match index {
+            Expr::Number(value, span) if !(0..len as i64).contains(value) => Err(
+                Diagnostic::error("E0213", "index out of bounds").with_label(
+                    span,
+                    format!("the length is {} but the index is {}", len, value),
+                ),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    // The type of the field, the struct has to be known by the time it is accessed
+    fn check_field(&mut self, field: &FieldExpr, ctx: FuncId) -> Result<Ty, Diagnostic> {
+        let ty = self.check_expr(&field.expr, ctx)?;
+        let id = match self.find(ty) {
+            Ty::Known(Type::Struct(id)) => id,
+            Ty::Known(typ) => {
+                return Err(Diagnostic::error(
+                    "E0217",
+                    format!("`{}` has no fields", typ.name(self.program)),
+                )
+                .with_label(field.expr.span(), "not a struct"));
+            }
+            Ty::Var(_) => {
+                return Err(
+                    Diagnostic::error("E0217", "can't tell which struct this is")
+                        .with_label(field.expr.span(), "the type isn't known here")
+                        .with_note("annotate the parameter with its struct, like `p: point`"),
+                );
+            }
+        };
+
+        let decl = &self.program.structs[id];
+        match decl.field(&field.field) {
+            Some(i) => Ok(Ty::Known(decl.fields[i].typ)),
+            None => Err(Diagnostic::error(
+                "E0214",
+                format!("struct `{}` has no field `{}`", decl.name, field.field),
+            )
+            .with_label(&field.span, "unknown field")
+            .with_secondary_label(&decl.span, "struct declared here")),
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr, ctx: FuncId) -> Result<Ty, Diagnostic> {
+        let ty = match expr {
+            Expr::Binary(bin_expr) => {
+                let left = self.check_expr(&bin_expr.left, ctx)?;
+                let right = self.check_expr(&bin_expr.right, ctx)?;
+                match bin_expr.op {
+                    OperatorType::Equals | OperatorType::NotEquals => {
+                        self.expect(left, right, bin_expr.right.span())
+                            .map_err(|err| err.with_note("both sides have to be the same type"))?;
+                        self.comparisons.push((left, bin_expr.span.clone()));
+                        Ty::Known(Type::Bool)
+                    }
+                    OperatorType::And | OperatorType::Or => {
+                        for (ty, operand) in [(left, &bin_expr.left), (right, &bin_expr.right)] {
+                            self.expect_condition(ty, operand)?;
+                        }
+                        Ty::Known(Type::Bool)
+                    }
+                    op => {
+                        let note = format!("{} only works on `int`", TokenType::Operator(op));
+                        for (ty, operand) in [(left, &bin_expr.left), (right, &bin_expr.right)] {
+                            self.expect(Ty::Known(Type::Int), ty, operand.span())
+                                .map_err(|err| err.with_note(note.clone()))?;
+                        }
+                        if op.is_comparison() {
+                            Ty::Known(Type::Bool)
+                        } else {
+                            Ty::Known(Type::Int)
+                        }
+                    }
+                }
+            }
+            Expr::Unary(unary_expr) => {
+                let ty = self.check_expr(&unary_expr.expr, ctx)?;
+                match unary_expr.op {
+                    OperatorType::Not => {
+                        self.expect_condition(ty, &unary_expr.expr)?;
+                        Ty::Known(Type::Bool)
+                    }
+                    op => {
+                        let note = format!("{} only works on `int`", TokenType::Operator(op));
+                        self.expect(Ty::Known(Type::Int), ty, unary_expr.expr.span())
+                            .map_err(|err| err.with_note(note))?;
+                        Ty::Known(Type::Int)
+                    }
+                }
+            }
+            // len is the only built-in that takes an array
+            Expr::Call(name, args, span)
+                if name == "len" && self.resolution.callee(span).is_none() =>
+            {
+                match &args[0] {
+                    Expr::Variable(name, span) => {
+                        let len = self.array_len(ctx, name, span)?;
+                        self.exprs
+                            .insert((span.start, span.end), Ty::Known(Type::Array(len)));
+                    }
+                    arg => {
+                        let found = self.check_expr(arg, ctx)?;
+                        let found = match self.find(found) {
+                            Ty::Known(typ) => typ,
+                            Ty::Var(_) => Type::Int,
+                        };
+                        return Err(
+                            Diagnostic::error("E0212", "`len` takes an array").with_label(
+                                arg.span(),
+                                format!("expected an array, found `{}`", found.name(self.program)),
+                            ),
+                        );
+                    }
+                }
+                Ty::Known(Type::Int)
+            }
+            Expr::Call(_, args, span) => {
+                let mut found = vec![];
+                for arg in args {
+                    found.push(self.check_expr(arg, ctx)?);
+                }
+
+                match self.resolution.callee(span) {
+                    Some(id) => {
+                        let params = self.resolution.info(id).params.clone();
+                        for ((arg, ty), binding) in args.iter().zip(found).zip(params) {
+                            let expected = self.binding(binding);
+                            self.expect(expected, ty, arg.span())?;
+                        }
+                        self.ret(id)
+                    }
+                    // print takes anything but structs and returns the number of bytes written
+                    None => {
+                        self.prints.push((found[0], args[0].span().clone()));
+                        Ty::Known(Type::Int)
+                    }
+                }
+            }
+            Expr::Number(..) => Ty::Known(Type::Int),
+            Expr::Str(..) => Ty::Known(Type::Str),
+            Expr::Bool(..) => Ty::Known(Type::Bool),
+            Expr::Variable(name, span) => {
+                let var = self.resolution.variable(ctx, span);
+                let ty = self.binding(var.id);
+                if let Ty::Known(Type::Array(_)) = self.find(ty) {
+                    return Err(Diagnostic::error("E0211", "arrays can't be used as values")
+                        .with_label(span, format!("`{}` is an array", name))
+                        .with_note("arrays can only be indexed or passed to `len`"));
+                }
+                ty
+            }
+            Expr::Array(array) => {
+                return Err(
+                    Diagnostic::error("E0211", "array literals can only define variables")
+                        .with_label(&array.span, "array literal")
+                        .with_note("use `looksmaxxing name = [...]|` to define an array"),
+                );
+            }
+            Expr::Index(index) => {
+                let len = self.array_len(ctx, &index.array, &index.span)?;
+                self.check_index(&index.index, len, ctx)?;
+                Ty::Known(Type::Int)
+            }
+            // Semantic analysis already checked that every field is given once
+            Expr::Struct(struct_expr) => {
+                let decl = &self.program.structs[struct_expr.id];
+                for init in &struct_expr.fields {
+                    let expected = decl.fields[decl.field(&init.name).unwrap()].typ;
+                    let found = self.check_expr(&init.expr, ctx)?;
+                    self.expect(Ty::Known(expected), found, init.expr.span())?;
+                }
+                Ty::Known(Type::Struct(struct_expr.id))
+            }
+            Expr::Field(field) => self.check_field(field, ctx)?,
+        };
+
+        let span = expr.span();
+        self.exprs.insert((span.start, span.end), ty);
+        Ok(ty)
+    }
+}
+
+// Whether the function body has a sigma with a value, not counting nested functions
+pub fn returns_value(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Return(expr, _) => expr.is_some(),
+        Stmt::If(if_stmt) => {
+            if_stmt
+                .branches
+                .iter()
+                .any(|branch| returns_value(&branch.body))
+                || returns_value(&if_stmt.else_body)
+        }
+        Stmt::While(while_stmt) => returns_value(&while_stmt.body),
+        Stmt::For(for_stmt) => returns_value(&for_stmt.body),
+        _ => false,
+    })
+}
+
+// Whether every path through the statements ends in a return
+pub fn always_returns(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Return(..) => true,
+        Stmt::If(if_stmt) => {
+            if_stmt
+                .branches
+                .iter()
+                .all(|branch| always_returns(&branch.body))
+                && always_returns(&if_stmt.else_body)
+        }
+        // edge nocap only ends through sigma unless something yeets out of it
+        Stmt::While(while_stmt) => {
+            matches!(while_stmt.condition, Expr::Bool(true, _)) && !breaks(&while_stmt.body)
+        }
+        _ => false,
+    })
+}
+
+// Whether a yeet in stmts jumps out of the loop they are the body of
+fn breaks(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Break(_) => true,
+        Stmt::If(if_stmt) => {
+            if_stmt.branches.iter().any(|branch| breaks(&branch.body)) || breaks(&if_stmt.else_body)
+        }
+        // A yeet in a nested loop or function belongs to that loop
+        _ => false,
+    })
+}
@@ -0,0 +1,111 @@
+// Out-of-range indices are caught at compile time when the index is a constant and at runtime
+// otherwise
+
+mod common;
+
+use common::{interpret, rejects, run};
+
+#[test]
+fn constant_indices_are_checked() {
+    for (i, (stmt, location)) in [
+        ("print(xs[3])|", "main.skl:2:10"),
+        ("print(xs[-1])|", "main.skl:2:10"),
+        ("xs[10] = 1|", "main.skl:2:4"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        rejects(
+            &format!("constant{}", i),
+            &format!("looksmaxxing xs = [1, 2, 3]|\n{}\n", stmt),
+            "E0213",
+            location,
+            "index out of bounds",
+        );
+    }
+}
+
+#[test]
+fn runtime_indices_are_checked() {
+    let source =
+        "looksmaxxing xs = [0; 4]|\nedge i in 0..5 >>\n    xs[i] = i|\n    print(xs[i])|\n<<\n";
+    let output = interpret("runtime", source, &[]);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n1\n2\n3\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0502"), "{}", stderr);
+}
+
+#[test]
+fn empty_arrays_are_rejected() {
+    for (i, (array, location)) in [("[]", "main.skl:1:19"), ("[0; 0]", "main.skl:1:23")]
+        .iter()
+        .enumerate()
+    {
+        rejects(
+            &format!("empty{}", i),
+            &format!("looksmaxxing xs = {}|\nprint(len(xs))|\n", array),
+            "E0108",
+            location,
+            "arrays can't be empty",
+        );
+    }
+}
+
+#[test]
+fn long_arrays_are_rejected() {
+    for (i, len) in ["65537", "9000000000000000000"].iter().enumerate() {
+        rejects(
+            &format!("long{}", i),
+            &format!("looksmaxxing xs = [0; {}]|\nprint(len(xs))|\n", len),
+            "E0109",
+            "main.skl:1:23",
+            "array is too long",
+        );
+    }
+
+    let output = interpret(
+        "longest",
+        "looksmaxxing xs = [0; 65536]|\nprint(len(xs))|\n",
+        &[],
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "65536\n");
+}
+
+#[test]
+fn compiled_backends_check_bounds_when_asked() {
+    let source = "looksmaxxing xs = [0; 4]|\nlooksmaxxing i = -3|\nprint(1)|\nprint(xs[i])|\n";
+    for backend in ["c", "asm"] {
+        let output = run(
+            backend,
+            source,
+            &["run", "--backend", backend, "--bounds-checks"],
+        );
+        assert_eq!(output.status.code(), Some(1), "{}", backend);
+        // run prints a newline after the program's own output
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n\n");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("E0502"), "{}: {}", backend, stderr);
+        assert!(stderr.contains("main.skl:4:10"), "{}: {}", backend, stderr);
+        assert!(
+            stderr.contains("the length is 4 but the index is -3"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn arrays_are_freed_with_their_block() {
+    // Every call defines an array, the caller's has to survive the ones its callees free
+    let source = "skibidi f(n) >>\n    looksmaxxing xs = [n; 3]|\n    sus n > 0 >>\n        f(n fanumtax 1)|\n    <<\n    sigma xs[2]|\n<<\nedge i in 0..3 >>\n    looksmaxxing ys = [i, i rizz 10]|\n    print(f(ys[1]))|\n<<\n";
+    let output = interpret("freed", source, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "10\n11\n12\n");
+}
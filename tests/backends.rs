@@ -0,0 +1,142 @@
+// The interpreter is the reference: every example has to print the same thing no matter which
+// backend compiles it
+
+mod common;
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn output(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_liamt-compiler"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap()
+}
+
+fn run(args: &[&str]) -> String {
+    let output = output(args);
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn examples() -> Vec<String> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut files: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("test")
+        })
+        .map(|path| format!("examples/{}", path.file_name().unwrap().to_str().unwrap()))
+        .collect();
+    files.push("program.skl".to_string());
+    files.sort();
+    files
+}
+
+// One test for all backends since the compiled backends share temporary file names
+#[test]
+fn backends_match_interpreter() {
+    for file in examples() {
+        let expected = run(&["run", "--interpret", &file]);
+
+        for backend in ["c", "asm"] {
+            let output = output(&["run", "--backend", backend, &file]);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            // The asm backend rejects programs with structs, anything else has to run
+            if backend == "asm" && !output.status.success() {
+                assert!(
+                    stderr.contains("E0300"),
+                    "{} with --backend asm: {}",
+                    file,
+                    stderr
+                );
+                assert!(
+                    stderr.contains("struct declared here"),
+                    "{}: {}",
+                    file,
+                    stderr
+                );
+                continue;
+            }
+
+            assert!(
+                output.status.success(),
+                "{} with --backend {}: {}",
+                file,
+                backend,
+                stderr
+            );
+            // run prints a newline after the program's own output
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                format!("{}\n", expected),
+                "{} with --backend {}",
+                file,
+                backend
+            );
+        }
+    }
+}
+
+// Both backends get their runtime checks from the same IR instructions but emit them
+// differently. They have to stop a program the same way
+#[test]
+fn runtime_checks_match() {
+    for (i, (source, flag)) in [
+        (
+            "looksmaxxing zero = 0|\nprint(1)|\nprint(7 mod zero)|\n",
+            None,
+        ),
+        (
+            "looksmaxxing big = 9223372036854775807|\nprint(big rizz 1)|\n",
+            None,
+        ),
+        (
+            "looksmaxxing big = 9223372036854775807|\nprint(big rizz 1)|\n",
+            Some("--overflow-checks"),
+        ),
+        (
+            "looksmaxxing small = -9223372036854775808|\nprint(-small)|\n",
+            Some("--overflow-checks"),
+        ),
+        (
+            "looksmaxxing xs = [1, 2]|\nedge i in 0..3 >>\n    print(xs[i])|\n<<\n",
+            Some("--bounds-checks"),
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let outputs: Vec<_> = ["c", "asm"]
+            .iter()
+            .map(|backend| {
+                let mut args = vec!["run", "--backend", backend];
+                args.extend(flag);
+                // The same name for both so that the paths in the messages match
+                common::run(&format!("checks{}", i), source, &args)
+            })
+            .collect();
+        let (c, asm) = (&outputs[0], &outputs[1]);
+        assert_eq!(c.status.code(), asm.status.code(), "{}", source);
+        assert_eq!(c.stdout, asm.stdout, "{}", source);
+        assert_eq!(
+            String::from_utf8_lossy(&c.stderr),
+            String::from_utf8_lossy(&asm.stderr),
+            "{}",
+            source
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// Helpers shared by the test suites. Every suite is a crate of its own and not all of them use
+// every helper
+#![allow(dead_code)]
+
+use std::process::{Command, Output};
+
+// Runs the compiler with args on source, saved as main.skl in a directory of its own that is
+// also the working directory since the compiled backends put their temporary files there. name
+// only has to be unique within a suite
+pub fn run(name: &str, source: &str, args: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("skibidi-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.skl");
+    std::fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_liamt-compiler"))
+        .args(args)
+        .arg(&path)
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+pub fn interpret(name: &str, source: &str, flags: &[&str]) -> Output {
+    let args: Vec<&str> = ["run", "--interpret"]
+        .iter()
+        .chain(flags)
+        .copied()
+        .collect();
+    run(name, source, &args)
+}
+
+// Checks that the interpreter rejects source before running any of it, and that the first error
+// is code with message, pointing at location like main.skl:2:5. Warnings can come before it
+pub fn rejects(name: &str, source: &str, code: &str, location: &str, message: &str) {
+    let output = interpret(name, source, &[]);
+    assert!(!output.status.success(), "{:?} was accepted", source);
+    assert!(output.stdout.is_empty(), "{:?} ran", source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut lines = stderr
+        .lines()
+        .skip_while(|line| !line.starts_with("error["));
+    assert_eq!(
+        lines.next(),
+        Some(format!("error[{}]: {}", code, message).as_str()),
+        "{:?}: {}",
+        source,
+        stderr
+    );
+    // The arrow line has the full path of main.skl
+    let arrow = lines.next().unwrap_or("");
+    assert!(
+        arrow.trim_start().starts_with("--> ") && arrow.ends_with(&format!("/{}", location)),
+        "{:?}: {}",
+        source,
+        stderr
+    );
+}
@@ -0,0 +1,67 @@
+// Dividing by a literal zero is a compile error, anything else is caught when the program runs
+// with the .skl location of the division
+
+mod common;
+
+use common::{interpret, rejects, run};
+
+#[test]
+fn literal_zero_is_rejected() {
+    for (i, expr) in ["5 mog 0", "5 mod 0"].iter().enumerate() {
+        rejects(
+            &format!("literal{}", i),
+            &format!("print({})|\n", expr),
+            "E0219",
+            "main.skl:1:13",
+            "attempt to divide by zero",
+        );
+    }
+}
+
+#[test]
+fn compiled_backends_report_where() {
+    let source = "looksmaxxing zero = 0|\nprint(1)|\nprint(7 mog zero)|\n";
+    for backend in ["c", "asm"] {
+        let output = run(backend, source, &["run", "--backend", backend]);
+        assert_eq!(output.status.code(), Some(1), "{}", backend);
+        // run prints a newline after the program's own output
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n\n");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("E0500"), "{}: {}", backend, stderr);
+        assert!(stderr.contains("main.skl:3:7"), "{}: {}", backend, stderr);
+    }
+}
+
+#[test]
+fn smallest_int_divided_by_minus_one_wraps() {
+    let source =
+        "looksmaxxing small = -9223372036854775808|\nprint(small mog -1)|\nprint(small mod -1)|\n";
+    let interpreted = interpret("interpreted", source, &[]);
+    assert_eq!(
+        String::from_utf8_lossy(&interpreted.stdout),
+        "-9223372036854775808\n0\n"
+    );
+    for backend in ["c", "asm"] {
+        let compiled = run(backend, source, &["run", "--backend", backend]);
+        assert!(
+            compiled.status.success(),
+            "{}: {}",
+            backend,
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&compiled.stdout),
+            "-9223372036854775808\n0\n\n"
+        );
+    }
+}
+
+#[test]
+fn crashes_are_reported() {
+    let source = "skibidi s(n) >>\n    sigma s(n rizz 1) rizz 1|\n<<\nprint(s(0))|\n";
+    let output = run("crash", source, &["run", "--backend", "asm"]);
+    assert_eq!(output.status.code(), Some(139));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0404"), "{}", stderr);
+    assert!(stderr.contains("SIGSEGV"), "{}", stderr);
+}
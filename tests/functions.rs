@@ -0,0 +1,153 @@
+// Functions can be called anywhere in the scope they are defined in, as long as the variables
+// they use are defined by the time they are called. sigma only works inside functions, and a
+// function that returns a value has to do so on every path
+
+mod common;
+
+use common::{interpret, rejects, run};
+
+#[test]
+fn calls_before_definition() {
+    let source = "print(twice(4))|\nskibidi twice(n) >>\n    sigma n rizz n|\n<<\n";
+    let output = interpret("forward", source, &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "8\n");
+}
+
+#[test]
+fn captured_variables_must_be_defined_first() {
+    for (i, (source, location, message)) in [
+        (
+            "print(get())|\nlooksmaxxing x = 1|\nskibidi get() >>\n    sigma x|\n<<\n",
+            "main.skl:1:7",
+            "`get` is called before `x` is defined",
+        ),
+        // Through a function that is defined before the variable
+        (
+            "skibidi outer() >>\n    sigma get()|\n<<\nprint(outer())|\nlooksmaxxing x = 1|\nskibidi get() >>\n    sigma x|\n<<\n",
+            "main.skl:4:7",
+            "`outer` is called before `x` is defined",
+        ),
+        (
+            "looksmaxxing x = get()|\nskibidi get() >>\n    sigma x|\n<<\n",
+            "main.skl:1:18",
+            "`get` is called before `x` is defined",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        rejects(&format!("early{}", i), source, "E0220", location, message);
+    }
+}
+
+#[test]
+fn sigma_needs_a_function() {
+    rejects(
+        "toplevel",
+        "print(1)|\nsigma 3|\n",
+        "E0221",
+        "main.skl:2:1",
+        "`sigma` outside of a function",
+    );
+}
+
+#[test]
+fn every_path_returns_a_value() {
+    // Falling off the end points at the function, a bare sigma at itself
+    for (i, (body, location)) in [
+        ("    sus n > 0 >>\n        sigma 1|\n    <<\n", "main.skl:1:1"),
+        (
+            "    edge i in 0..n >>\n        sigma i|\n    <<\n",
+            "main.skl:1:1",
+        ),
+        // The loop can be left with yeet
+        (
+            "    edge nocap >>\n        sus n > 3 >>\n            yeet|\n        <<\n        sigma 1|\n    <<\n",
+            "main.skl:1:1",
+        ),
+        // A bare sigma counts as a path without a value
+        (
+            "    sus nocap >>\n        sigma|\n    <<\n    sigma 3|\n",
+            "main.skl:3:9",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let source = format!("skibidi f(n) >>\n{}<<\nprint(f(1))|\n", body);
+        rejects(
+            &format!("paths{}", i),
+            &source,
+            "E0222",
+            location,
+            "function `f` doesn't return a value on every path",
+        );
+    }
+
+    let source = "skibidi f(n) >>\n    sus n > 0 >>\n        sigma 1|\n    <<\n    sussy >>\n        sigma 2|\n    <<\n<<\nprint(f(1))|\n";
+    let output = interpret("paths", source, &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+}
+
+#[test]
+fn unreachable_statements_warn() {
+    let source = "skibidi f(n) >>\n    sigma n|\n    print(n)|\n<<\nprint(f(1))|\n";
+    let output = interpret("unreachable", source, &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("warning[W0200]"), "{}", stderr);
+}
+
+#[test]
+fn calls_nest_a_hundred_thousand_deep() {
+    // s(n) nests n + 1 calls
+    let source = "skibidi s(n) >>\n    sus n == 0 >>\n        sigma 0|\n    <<\n    sigma s(n fanumtax 1) rizz 1|\n<<\nprint(s(99999))|\n";
+    let output = interpret("deep", source, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "99999\n");
+
+    let output = interpret("deeper", &source.replace("99999", "100000"), &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0501"), "{}", stderr);
+    assert!(
+        stderr.contains("calls can only nest 100000 deep"),
+        "{}",
+        stderr
+    );
+
+    let source = "skibidi s(n) >>\n    sigma s(n rizz 1)|\n<<\nprint(s(0))|\n";
+    let output = interpret("endless", source, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0501"), "{}", stderr);
+}
+
+#[test]
+fn compiled_calls_nest_a_hundred_thousand_deep() {
+    // Registers share stack words, so a frame is a few words and fits in the usual 8 MB stack
+    let source = "skibidi s(n) >>\n    looksmaxxing rest = n fanumtax 1|\n    sus n == 0 >>\n        sigma 0|\n    <<\n    sigma s(rest) rizz 1|\n<<\nprint(s(100000))|\n";
+    for backend in ["c", "asm"] {
+        let output = run(backend, source, &["run", "--backend", backend]);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            backend,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        // run prints a newline after the program's own output
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "100000\n\n",
+            "{}",
+            backend
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// Errors found while splitting the source into tokens, each pointing at where the bad token starts
+
+mod common;
+
+use common::{interpret, rejects};
+
+#[test]
+fn nested_block_comments_have_to_be_closed() {
+    // The inner */ only closes the inner comment, so the outer one runs to the end of the file
+    rejects(
+        "comment",
+        "print(1)|\n/* outer /* inner */ still open\nprint(2)|\n",
+        "E0002",
+        "main.skl:2:1",
+        "unterminated block comment",
+    );
+
+    let output = interpret("closed", "/* a /* b */ c */\nprint(2)|\n", &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+}
+
+#[test]
+fn strings_have_to_be_closed_on_their_line() {
+    rejects(
+        "unterminated",
+        "print(\"abc)|\n",
+        "E0003",
+        "main.skl:1:7",
+        "unterminated string literal",
+    );
+    // A string can't continue on the next line
+    rejects(
+        "newline",
+        "print(\"line\nend\")|\n",
+        "E0003",
+        "main.skl:1:7",
+        "unterminated string literal",
+    );
+    rejects(
+        "eof",
+        "print(\"line",
+        "E0003",
+        "main.skl:1:7",
+        "unterminated string literal",
+    );
+}
+
+#[test]
+fn unknown_escapes_are_rejected() {
+    rejects(
+        "escape",
+        "print(\"a\\qb\")|\n",
+        "E0004",
+        "main.skl:1:9",
+        "unknown escape sequence",
+    );
+    rejects(
+        "escape_end",
+        "print(\"a\\",
+        "E0004",
+        "main.skl:1:9",
+        "unknown escape sequence",
+    );
+
+    let output = interpret("escapes", "print(\"a\\tb\\\\c\\\"d\")|\n", &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\tb\\c\"d\n");
+}
+
+#[test]
+fn bare_underscores_are_rejected() {
+    rejects(
+        "underscore",
+        "looksmaxxing _ = 1|\n",
+        "E0006",
+        "main.skl:1:14",
+        "expected a name after `_`",
+    );
+    rejects(
+        "underscore_expr",
+        "print(_)|\n",
+        "E0006",
+        "main.skl:1:7",
+        "expected a name after `_`",
+    );
+    rejects(
+        "underscores",
+        "looksmaxxing __ = 1|\n",
+        "E0006",
+        "main.skl:1:14",
+        "expected a name after `_`",
+    );
+
+    let output = interpret("underscore_name", "looksmaxxing _a = 1|\nprint(_a)|\n", &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+}
+
+#[test]
+fn underscores_join_words() {
+    let source = "looksmaxxing my_var = 1|\nlooksmaxxing _my__other_ = 2|\nprint(my_var rizz _my__other_)|\n";
+    let output = interpret("joined", source, &["--deny-warnings"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+}
+
+#[test]
+fn columns_count_characters() {
+    // é is two bytes, the column and the underline count it once
+    rejects(
+        "column",
+        "print(\"é\")|é\n",
+        "E0001",
+        "main.skl:1:12",
+        "unexpected character 'é'",
+    );
+
+    let output = interpret("underline", "print(\"é\" rizz 1)|\n", &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0206"), "{}", stderr);
+    assert!(
+        stderr.contains("  |       ^^^ expected `int`, found `string`"),
+        "{}",
+        stderr
+    );
+}
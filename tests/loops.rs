@@ -0,0 +1,43 @@
+// yeet and bet only make sense inside an edge loop, and a function body starts outside of
+// every loop even when it is defined in one
+
+mod common;
+
+use common::{interpret, rejects};
+
+#[test]
+fn yeet_and_bet_outside_a_loop_are_rejected() {
+    for (i, (source, keyword, location)) in [
+        ("yeet|\n", "yeet", "main.skl:1:1"),
+        ("bet|\n", "bet", "main.skl:1:1"),
+        ("sus nocap >>\n    bet|\n<<\n", "bet", "main.skl:2:5"),
+        (
+            "edge nocap >>\n    skibidi f() >>\n        yeet|\n    <<\n    yeet|\n<<\n",
+            "yeet",
+            "main.skl:3:9",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        rejects(
+            &format!("outside{}", i),
+            source,
+            "E0210",
+            location,
+            &format!("`{}` outside of a loop", keyword),
+        );
+    }
+}
+
+#[test]
+fn yeet_and_bet_inside_a_loop_are_accepted() {
+    let source = "edge i in 0..5 >>\n    sus i == 1 >>\n        bet|\n    <<\n    sus i == 3 >>\n        yeet|\n    <<\n    print(i)|\n<<\n";
+    let output = interpret("inside", source, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n2\n");
+}
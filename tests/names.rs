@@ -0,0 +1,82 @@
+// Names in the program never clash with what the generated code declares, whether that comes
+// from the C headers, the runtime checks, the prefixed function names or the C keywords
+
+mod common;
+
+use common::run;
+
+#[test]
+fn functions_named_like_c_library_functions() {
+    let source = "skibidi remove(x) >>
+    sigma x rizz 1|
+<<
+skibidi fflush(x) >>
+    sigma x gyatt 2|
+<<
+skibidi abs(x) >>
+    sigma x|
+<<
+skibidi div(x) >>
+    sigma x mog 2|
+<<
+print(remove(1))|
+print(fflush(2))|
+print(abs(3))|
+print(div(8))|
+";
+    for backend in ["c", "asm"] {
+        let output = run(backend, source, &["run", "--backend", backend]);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            backend,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        // run prints a newline after the program's own output
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n4\n3\n4\n\n");
+    }
+}
+
+#[test]
+fn variables_named_like_c_keywords() {
+    let source = "looksmaxxing typeof = 1|\nlooksmaxxing bool = 2|\nprint(typeof rizz bool)|\n";
+    let output = run("keywords", source, &["run"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n\n");
+}
+
+#[test]
+fn underscore_names_are_not_c_macros() {
+    let source = "looksmaxxing _EOF = 1|\nlooksmaxxing _NULL = 2|\nprint(_EOF rizz _NULL)|\n";
+    let output = run("underscore", source, &["run"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n\n");
+}
+
+#[test]
+fn variables_named_like_generated_names() {
+    // rt_div is the runtime's division and skl_half is what half is called in C
+    let source = "skibidi half(x) >>
+    looksmaxxing rt_div = x mog 2|
+    sigma rt_div|
+<<
+looksmaxxing skl_half = half(10)|
+looksmaxxing static_assert = half(skl_half)|
+print(static_assert)|
+";
+    let output = run("generated", source, &["run"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n\n");
+}
@@ -0,0 +1,77 @@
+// Ints are 64 bits, literals that don't fit are rejected and arithmetic wraps around unless
+// --overflow-checks is given
+
+mod common;
+
+use common::{interpret, rejects, run};
+
+#[test]
+fn literals_must_fit() {
+    // Only the smallest int's magnitude is larger than the largest int, and only with a minus
+    for (i, (literal, location)) in [
+        ("9223372036854775808", "main.skl:1:7"),
+        ("-9223372036854775809", "main.skl:1:8"),
+        ("-(9223372036854775808)", "main.skl:1:9"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        rejects(
+            &format!("literal{}", i),
+            &format!("print({})|\n", literal),
+            "E0005",
+            location,
+            "integer literal is too large",
+        );
+    }
+
+    let output = interpret(
+        "smallest",
+        "print(-9223372036854775808)|\nprint(9223372036854775807)|\n",
+        &[],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "-9223372036854775808\n9223372036854775807\n"
+    );
+}
+
+#[test]
+fn arithmetic_wraps_by_default() {
+    let source = "looksmaxxing big = 9223372036854775807|\nprint(big rizz 1)|\n";
+    let output = interpret("wraps", source, &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "-9223372036854775808\n"
+    );
+}
+
+#[test]
+fn overflow_checks_stop_the_program() {
+    let source = "looksmaxxing big = 9223372036854775807|\nprint(big gyatt 2)|\n";
+    let output = interpret("checked", source, &["--overflow-checks"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0503"), "{}", stderr);
+}
+
+#[test]
+fn compiled_backends_check_overflow_too() {
+    let source = "looksmaxxing big = 9223372036854775807|\nprint(1)|\nprint(big rizz 1)|\n";
+    for backend in ["c", "asm"] {
+        let output = run(
+            backend,
+            source,
+            &["run", "--backend", backend, "--overflow-checks"],
+        );
+        assert_eq!(output.status.code(), Some(1), "{}", backend);
+        // run prints a newline after the program's own output
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n\n");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("E0503"), "{}: {}", backend, stderr);
+        assert!(stderr.contains("main.skl:3:7"), "{}: {}", backend, stderr);
+    }
+}
@@ -0,0 +1,123 @@
+// Every case is printed by one program run through the interpreter, so a wrong parse shows up
+// as a wrong value next to the expression that produced it
+
+mod common;
+
+use common::{interpret, rejects};
+
+// Expression and what it evaluates to, booleans print as 1 and 0
+const CASES: &[(&str, i64)] = &[
+    ("1 rizz 2 gyatt 3", 7),
+    ("2 gyatt 3 rizz 1", 7),
+    ("(1 rizz 2) gyatt 3", 9),
+    ("10 fanumtax 4 fanumtax 3", 3),
+    ("10 fanumtax (4 fanumtax 3)", 9),
+    ("100 mog 10 mog 5", 2),
+    ("2 gyatt 6 mog 4", 3),
+    ("2 gyatt (6 mog 4)", 2),
+    ("10 fanumtax 2 gyatt 3 rizz 4", 8),
+    ("1 rizz 2 == 3", 1),
+    ("3 == 1 rizz 2", 1),
+    ("2 gyatt 3 == 6", 1),
+    ("2 gyatt 3 != 6", 0),
+    ("1 rizz 1 == 4 mog 2", 1),
+    ("(1 == 1) == (2 == 2)", 1),
+    ("(1 == 2) != (2 == 2)", 1),
+    ("((((7))))", 7),
+    ("1 < 2", 1),
+    ("2 <= 2", 1),
+    ("3 > 4", 0),
+    ("4 >= 5", 0),
+    ("1 rizz 2 < 2 gyatt 2", 1),
+    ("1 < 2 == 3 < 4", 1),
+    ("1 < 2 != 2 > 3", 1),
+    ("(1 < 2) == (3 > 4)", 0),
+    ("nocap or cap and cap", 1),
+    ("(nocap or cap) and cap", 0),
+    ("not cap and cap", 0),
+    ("not (cap and cap)", 1),
+    ("not 1 == 2", 1),
+    ("not not nocap", 1),
+    ("1 < 2 and 2 < 3 or cap", 1),
+    ("cap or 1 rizz 1 == 2", 1),
+    ("-5", -5),
+    ("-2 gyatt 3", -6),
+    ("-(2 rizz 3)", -5),
+    ("4 fanumtax -2", 6),
+    ("- -7", 7),
+    ("-(-7)", 7),
+    ("-2 < -1", 1),
+    ("17 mod 5", 2),
+    ("-17 mod 5", -2),
+    ("2 rizz 7 mod 4", 5),
+    ("12 bitand 10", 8),
+    ("12 bitor 10", 14),
+    ("12 bitxor 10", 6),
+    ("bitnot 0", -1),
+    ("bitnot 5 rizz 1", -5),
+    ("1 shl 4", 16),
+    ("-32 shr 2", -8),
+    ("1 shl 2 rizz 1", 8),
+    ("1 bitor 2 bitxor 3 bitand 6", 1),
+    ("6 bitand 1 == 0", 1),
+];
+
+#[test]
+fn operators_follow_precedence_and_associativity() {
+    let source: String = CASES
+        .iter()
+        .map(|(expr, _)| format!("print({})|\n", expr))
+        .collect();
+    let output = interpret("cases", &source, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), CASES.len());
+    for ((expr, expected), line) in CASES.iter().zip(lines) {
+        assert_eq!(line, expected.to_string(), "{}", expr);
+    }
+}
+
+#[test]
+fn comparisons_do_not_chain() {
+    // Pointing at the second comparison
+    for (i, (expr, location)) in [
+        ("1 == 1 == 1", "main.skl:1:14"),
+        ("1 != 2 == 1", "main.skl:1:14"),
+        ("1 == 2 rizz 3 != 4", "main.skl:1:21"),
+        ("1 < 2 < 3", "main.skl:1:13"),
+        ("1 >= 2 <= 3", "main.skl:1:14"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        rejects(
+            &format!("chain{}", i),
+            &format!("print({})|\n", expr),
+            "E0105",
+            location,
+            "comparison operators can't be chained",
+        );
+    }
+}
+
+#[test]
+fn constant_shift_amounts_are_checked() {
+    for (i, (expr, amount)) in [("1 shl 64", "64"), ("1 shr -1", "-1"), ("1 shl -(1)", "-1")]
+        .iter()
+        .enumerate()
+    {
+        rejects(
+            &format!("shift{}", i),
+            &format!("print({})|\n", expr),
+            "E0209",
+            "main.skl:1:13",
+            &format!("shift amount {} is out of range", amount),
+        );
+    }
+}
@@ -0,0 +1,112 @@
+// Structs are values, so every definition, argument and field gets its own copy
+
+mod common;
+
+use common::{interpret, rejects};
+
+#[test]
+fn structs_are_copied() {
+    let source = "gang point >> x: int, y: int <<
+gang pair >> a: point, b: point <<
+skibidi bump(p: point) >>
+    p.x = p.x rizz 1|
+    sigma p.x|
+<<
+looksmaxxing p = point(x: 1, y: 2)|
+looksmaxxing q = p|
+q.x = 10|
+looksmaxxing both = pair(a: p, b: p)|
+both.a.y = 20|
+print(bump(p))|
+print(p.x)|
+print(p.y)|
+print(q.x)|
+print(both.a.y)|
+print(both.b.y)|
+";
+    let output = interpret("copies", source, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "2\n1\n2\n10\n20\n2\n"
+    );
+}
+
+#[test]
+fn struct_literals_need_every_field_once() {
+    for (i, (literal, code, location, message)) in [
+        (
+            "point(x: 1)",
+            "E0216",
+            "main.skl:2:18",
+            "missing fields `y` for struct `point`",
+        ),
+        (
+            "point(x: 1, y: 2, z: 3)",
+            "E0214",
+            "main.skl:2:36",
+            "struct `point` has no field `z`",
+        ),
+        (
+            "point(x: 1, x: 2, y: 3)",
+            "E0215",
+            "main.skl:2:30",
+            "field `x` is already given",
+        ),
+        (
+            "point(x: 1, y: nocap)",
+            "E0206",
+            "main.skl:2:33",
+            "mismatched types",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let source = format!(
+            "gang point >> x: int, y: int <<\nlooksmaxxing p = {}|\n",
+            literal
+        );
+        rejects(&format!("literal{}", i), &source, code, location, message);
+    }
+}
+
+#[test]
+fn structs_outlive_the_calls_that_made_them() {
+    // A returned struct is copied out of the call before its records are freed, and assigning
+    // through a capture overwrites the caller's struct in place
+    let source = "gang point >> x: int, y: int <<
+gang pair >> a: point, b: point <<
+looksmaxxing outer = pair(a: point(x: 0, y: 0), b: point(x: 0, y: 0))|
+skibidi make(n) >>
+    looksmaxxing p = point(x: n, y: n rizz 1)|
+    sigma pair(a: p, b: point(x: p.y, y: n))|
+<<
+skibidi grab(n) >>
+    outer = make(n)|
+    outer.b = point(x: n, y: n)|
+<<
+looksmaxxing last = make(0)|
+edge i in 0..3 >>
+    looksmaxxing made = make(i)|
+    last = made|
+    grab(i rizz 10)|
+<<
+print(last.a.x)|
+print(last.b.x)|
+print(make(7).b.y)|
+print(outer.a.y)|
+print(outer.b.x)|
+";
+    let output = interpret("outlive", source, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n3\n7\n13\n12\n");
+}
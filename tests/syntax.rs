@@ -0,0 +1,31 @@
+// The parser skips to the next statement after a syntax error, so one run reports every
+// mistake in the file instead of only the first
+
+mod common;
+
+use common::interpret;
+
+#[test]
+fn every_syntax_error_is_reported_once() {
+    let source = "looksmaxxing a = |\nprint(1 rizz)|\nsus 1 >>\n    looksmaxxing = 2|\n<<\nprint(a)|\nlooksmaxxing b = (3|\n";
+    let output = interpret("recovery", source, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("error[").count(), 4, "{}", stderr);
+    for location in [
+        "main.skl:1:18",
+        "main.skl:2:13",
+        "main.skl:4:18",
+        "main.skl:7:20",
+    ] {
+        assert_eq!(
+            stderr.matches(location).count(),
+            1,
+            "{}: {}",
+            location,
+            stderr
+        );
+    }
+    // Nothing runs when the program doesn't parse
+    assert!(output.stdout.is_empty());
+}
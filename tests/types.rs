@@ -0,0 +1,108 @@
+// Every value has a single type that is inferred from how it is used, and programs that use a
+// value as two different types are rejected before any backend sees them
+
+mod common;
+
+use common::rejects;
+
+#[test]
+fn mismatched_types_are_rejected() {
+    for (i, (source, code, location, message)) in [
+        (
+            "looksmaxxing x = 1|\nx = \"hi\"|\nprint(x)|\n",
+            "E0206",
+            "main.skl:2:5",
+            "mismatched types",
+        ),
+        (
+            "skibidi f(s: string): int >>\n    sigma s|\n<<\nprint(f(\"a\"))|\n",
+            "E0206",
+            "main.skl:2:11",
+            "mismatched types",
+        ),
+        (
+            "skibidi f(n: bool) >>\n    sus n >>\n        sigma 1|\n    <<\n    sigma 0|\n<<\nprint(f(2))|\n",
+            "E0206",
+            "main.skl:7:9",
+            "mismatched types",
+        ),
+        (
+            "print(1 rizz nocap)|\n",
+            "E0206",
+            "main.skl:1:14",
+            "mismatched types",
+        ),
+        (
+            "sus 1 >>\n    print(1)|\n<<\n",
+            "E0206",
+            "main.skl:1:5",
+            "mismatched types",
+        ),
+        (
+            "skibidi f() >>\n    print(1)|\n<<\nsus f() >>\n    print(2)|\n<<\n",
+            "E0206",
+            "main.skl:4:5",
+            "mismatched types",
+        ),
+        // Called before the definition shows that it never returns a value
+        (
+            "sus f() >>\n    print(2)|\n<<\nskibidi f() >>\n    print(1)|\n<<\n",
+            "E0208",
+            "main.skl:4:1",
+            "function `f` doesn't return a value but is used as `bool`",
+        ),
+        (
+            "print(\"a\" == \"b\")|\n",
+            "E0207",
+            "main.skl:1:7",
+            "strings can't be compared",
+        ),
+        (
+            "gang p >> x: int <<\nlooksmaxxing a = p(x: 1)|\nprint(a == a)|\n",
+            "E0207",
+            "main.skl:3:7",
+            "structs can't be compared",
+        ),
+        (
+            "looksmaxxing xs = [1, 2]|\nlooksmaxxing ys = xs|\nprint(ys[0])|\n",
+            "E0211",
+            "main.skl:2:19",
+            "arrays can't be used as values",
+        ),
+        (
+            "print([1, 2])|\n",
+            "E0211",
+            "main.skl:1:7",
+            "array literals can only define variables",
+        ),
+        (
+            "looksmaxxing x = 1|\nprint(x[0])|\n",
+            "E0212",
+            "main.skl:2:7",
+            "`x` is not an array",
+        ),
+        (
+            "print(len(3))|\n",
+            "E0212",
+            "main.skl:1:11",
+            "`len` takes an array",
+        ),
+        (
+            "skibidi f(p) >>\n    sigma p.x|\n<<\nprint(f(1))|\n",
+            "E0217",
+            "main.skl:2:11",
+            "can't tell which struct this is",
+        ),
+        (
+            "gang p >> x: int <<\nlooksmaxxing a = p(x: 1)|\nprint(a)|\n",
+            "E0218",
+            "main.skl:3:7",
+            "structs can't be printed",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        rejects(&format!("mismatch{}", i), source, code, location, message);
+    }
+}
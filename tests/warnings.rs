@@ -0,0 +1,63 @@
+// Unused variables, parameters and functions are warned about unless their name starts with _,
+// and --deny-warnings makes any warning stop the compilation
+
+mod common;
+
+use common::interpret;
+
+#[test]
+fn unused_definitions_warn() {
+    for (i, (source, expected)) in [
+        ("looksmaxxing x = 1|\n", "unused variable `x`"),
+        (
+            "skibidi f(n) >>\n    sigma 1|\n<<\nprint(f(2))|\n",
+            "unused parameter `n`",
+        ),
+        // Calling itself doesn't count
+        (
+            "skibidi f(n) >>\n    sigma f(n)|\n<<\n",
+            "unused function `f`",
+        ),
+        // Neither is reachable from the top level, even though they call each other
+        (
+            "skibidi isEven(n) >>\n    sus n == 0 >>\n        sigma 1|\n    <<\n    sigma isOdd(n fanumtax 1)|\n<<\nskibidi isOdd(n) >>\n    sus n == 0 >>\n        sigma 0|\n    <<\n    sigma isEven(n fanumtax 1)|\n<<\n",
+            "unused function `isOdd`",
+        ),
+        (
+            "edge i in 0..2 >>\n    print(1)|\n<<\n",
+            "unused variable `i`",
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let output = interpret(&format!("unused{}", i), source, &[]);
+        assert!(output.status.success(), "{}", source);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{}: {}", source, stderr);
+    }
+}
+
+#[test]
+fn underscore_names_are_exempt() {
+    // g is only called from _f, which is kept on purpose
+    let source = "looksmaxxing _x = 1|\nskibidi g() >>\n    sigma 1|\n<<\nskibidi _f(_n) >>\n    sigma g()|\n<<\nedge _i in 0..2 >>\n    print(2)|\n<<\n";
+    let output = interpret("exempt", source, &["--deny-warnings"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n2\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn deny_warnings_stops_compilation() {
+    let output = interpret(
+        "deny",
+        "looksmaxxing x = 1|\nprint(2)|\n",
+        &["--deny-warnings"],
+    );
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("W0201"), "{}", stderr);
+    assert!(stderr.contains("E0403"), "{}", stderr);
+}